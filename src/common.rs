@@ -8,6 +8,41 @@ pub type Integral = usize;
 
 pub type Float = f64;
 
+/// A location in the source code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    /// The byte offset of the first character.
+    pub start: usize,
+    /// The byte offset one past the last character.
+    pub end: usize,
+    /// The line of the first character, starting at 1.
+    pub line: usize,
+    /// The column of the first character, starting at 1.
+    pub column: usize,
+}
+
+impl Span {
+    /// The span from the start of this span to the end of the other.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Unary {
     Fetch,
@@ -30,8 +65,9 @@ pub enum Binary {
     Abort,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum Expression {
+    #[default]
     Undefined,
     Number(Float),
     List(Vec<Expression>),
@@ -39,11 +75,13 @@ pub enum Expression {
     Unary {
         operator: Unary,
         operand: Box<Expression>,
+        span: Span,
     },
     Binary {
         operator: Binary,
         left: Box<Expression>,
         right: Box<Expression>,
+        span: Span,
     },
     PointerIntoList {
         address: usize,
@@ -52,12 +90,6 @@ pub enum Expression {
     Stub,
 }
 
-impl Default for Expression {
-    fn default() -> Expression {
-        Expression::Undefined
-    }
-}
-
 impl std::fmt::Display for Expression {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -80,13 +112,16 @@ impl std::fmt::Display for Expression {
                 }
                 write!(f, "]")
             }
-            Expression::Unary { operator, operand } => {
+            Expression::Unary {
+                operator, operand, ..
+            } => {
                 write!(f, "{operator:?}({operand})")
             }
             Expression::Binary {
                 operator,
                 left,
                 right,
+                ..
             } => write!(f, "{operator:?}({left} {right})"),
             Expression::Stub => write!(f, "<?>"),
         }
@@ -97,4 +132,5 @@ impl std::fmt::Display for Expression {
 pub struct Instruction {
     pub label: Integral,
     pub expression: Expression,
+    pub span: Span,
 }
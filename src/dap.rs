@@ -0,0 +1,600 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use crate::protocol;
+use numpad::common::*;
+use numpad::debugger::{span_of, Debugger, Granularity, Stop};
+use numpad::lexer;
+use numpad::{deparse, InputBuffer, Limits, Machine, OutputBuffer};
+
+use log::*;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+const THREAD_ID: usize = 1;
+const CALL_SCOPE: usize = 1;
+const TAPE_SCOPE: usize = 2;
+
+/// Speak the Debug Adapter Protocol over stdin and stdout until the client
+/// disconnects. Requests are read on a separate thread, so that a pause
+/// request can interrupt an evaluation that is running.
+pub fn serve() -> anyhow::Result<()> {
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    {
+        let interrupt = interrupt.clone();
+        std::thread::spawn(move || {
+            if let Err(error) = read_messages(sender, interrupt) {
+                error!("{}", error);
+            }
+        });
+    }
+    let connection = &mut Connection { seq: 0 };
+    let session = &mut Session::new(interrupt);
+    for request in receiver {
+        if !session.handle(&request, connection)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_messages(
+    sender: mpsc::Sender<Value>,
+    interrupt: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let mut stdin = std::io::stdin().lock();
+    while let Some(message) = protocol::read_message(&mut stdin)? {
+        let command = message["command"].as_str();
+        if matches!(command, Some("pause" | "disconnect" | "terminate")) {
+            interrupt.store(true, Ordering::SeqCst);
+        }
+        if sender.send(message).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+struct Connection {
+    seq: u64,
+}
+
+impl Connection {
+    fn send(&mut self, mut message: Value) -> anyhow::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        protocol::write_message(&message)
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> anyhow::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": true,
+            "command": request["command"],
+            "body": body,
+        }))
+    }
+
+    fn refuse(&mut self, request: &Value, message: &str) -> anyhow::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": false,
+            "command": request["command"],
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> anyhow::Result<()> {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }))
+    }
+}
+
+/// The program that was launched.
+struct Program {
+    path: std::path::PathBuf,
+    source: String,
+    debugger: Debugger,
+}
+
+struct Session {
+    program: Option<Program>,
+    output: OutputBuffer,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    interrupt: Arc<AtomicBool>,
+    is_configured: bool,
+    is_stop_on_entry: bool,
+}
+
+impl Session {
+    fn new(interrupt: Arc<AtomicBool>) -> Session {
+        Session {
+            program: None,
+            output: OutputBuffer::new(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            interrupt,
+            is_configured: false,
+            is_stop_on_entry: false,
+        }
+    }
+
+    /// Handle a single request. Returns false if the session is over.
+    fn handle(
+        &mut self,
+        request: &Value,
+        connection: &mut Connection,
+    ) -> anyhow::Result<bool> {
+        let arguments = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default();
+        match command {
+            "initialize" => {
+                connection.respond(
+                    request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsDataBreakpoints": true,
+                        "supportsSteppingGranularity": true,
+                        "supportsTerminateRequest": true,
+                    }),
+                )?;
+                connection.event("initialized", json!({}))?;
+            }
+            "launch" => {
+                let path = match arguments["program"].as_str() {
+                    Some(path) => std::path::PathBuf::from(path),
+                    None => {
+                        connection.refuse(request, "Expected a program")?;
+                        return Ok(true);
+                    }
+                };
+                let source = match std::fs::read_to_string(&path) {
+                    Ok(source) => source,
+                    Err(error) => {
+                        connection.refuse(request, &error.to_string())?;
+                        return Ok(true);
+                    }
+                };
+                let (instructions, diagnostics) = numpad::compile_all(&source);
+                if !diagnostics.is_empty() {
+                    let name = path.to_string_lossy();
+                    let message =
+                        crate::render_all(&diagnostics, &source, Some(&name));
+                    connection.refuse(request, &message)?;
+                    return Ok(true);
+                }
+                let mut machine = Machine::create(instructions);
+                // As on the command line, runtime errors are only fatal if
+                // the program is launched with `"strict": true`.
+                let is_strict =
+                    arguments["strict"].as_bool().unwrap_or_default();
+                machine.set_lenient(!is_strict);
+                let limit = |name: &str| {
+                    arguments[name].as_u64().map(|limit| limit as usize)
+                };
+                machine.set_limits(Limits {
+                    max_steps: arguments["maxSteps"].as_u64(),
+                    max_tape_size: limit("maxTapeSize"),
+                    max_call_depth: limit("maxCallDepth"),
+                    max_list_len: limit("maxListLen"),
+                });
+                machine.set_output(Box::new(self.output.clone()));
+                let input = arguments["input"].as_str().unwrap_or_default();
+                machine.set_input(Box::new(InputBuffer::new(input)));
+                let mut debugger = Debugger::new(machine);
+                debugger.set_interrupt(self.interrupt.clone());
+                self.is_stop_on_entry =
+                    arguments["stopOnEntry"].as_bool().unwrap_or_default();
+                self.program = Some(Program {
+                    path,
+                    source,
+                    debugger,
+                });
+                self.apply_breakpoints();
+                connection.respond(request, json!({}))?;
+                if self.is_configured {
+                    self.begin(connection)?;
+                }
+            }
+            "setBreakpoints" => {
+                // Breakpoints can be set before the program is launched, so
+                // the source is read from the path that comes with them.
+                let path = arguments["source"]["path"].as_str();
+                let source = path
+                    .and_then(|path| std::fs::read_to_string(path).ok())
+                    .unwrap_or_default();
+                let lines = instruction_lines(&source);
+                let requested = arguments["breakpoints"].as_array();
+                let mut breakpoints = Vec::new();
+                self.breakpoints.clear();
+                for breakpoint in requested.into_iter().flatten() {
+                    let line = breakpoint["line"].as_u64().unwrap_or_default();
+                    let line = line as usize;
+                    let found = lines.iter().find(|(_, first, last)| {
+                        *first <= line && line <= *last
+                    });
+                    match found {
+                        Some((label, first, _)) => {
+                            self.breakpoints.insert(*label);
+                            let message =
+                                format!("Pauses when {} is called", label);
+                            breakpoints.push(json!({
+                                "verified": true,
+                                "line": first,
+                                "message": message,
+                            }));
+                        }
+                        None => breakpoints.push(json!({
+                            "verified": false,
+                            "line": line,
+                            "message": "There is no instruction on this line",
+                        })),
+                    }
+                }
+                self.apply_breakpoints();
+                connection
+                    .respond(request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "dataBreakpointInfo" => {
+                let reference = arguments["variablesReference"].as_u64();
+                let name = arguments["name"].as_str().unwrap_or_default();
+                let address = name.parse::<usize>().ok();
+                let body = match address {
+                    Some(address) if reference == Some(TAPE_SCOPE as u64) => {
+                        json!({
+                            "dataId": address.to_string(),
+                            "description": format!("Writes to {}", address),
+                            "accessTypes": ["write"],
+                        })
+                    }
+                    _ => {
+                        let description =
+                            "Only addresses on the tape can be watched";
+                        json!({ "dataId": null, "description": description })
+                    }
+                };
+                connection.respond(request, body)?;
+            }
+            "setDataBreakpoints" => {
+                let requested = arguments["breakpoints"].as_array();
+                let mut breakpoints = Vec::new();
+                self.watchpoints.clear();
+                for breakpoint in requested.into_iter().flatten() {
+                    let data_id = breakpoint["dataId"].as_str();
+                    match data_id.and_then(|id| id.parse::<usize>().ok()) {
+                        Some(address) => {
+                            self.watchpoints.insert(address);
+                            breakpoints.push(json!({ "verified": true }));
+                        }
+                        None => breakpoints.push(json!({ "verified": false })),
+                    }
+                }
+                self.apply_breakpoints();
+                connection
+                    .respond(request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "setExceptionBreakpoints" => {
+                connection.respond(request, json!({ "breakpoints": [] }))?;
+            }
+            "configurationDone" => {
+                connection.respond(request, json!({}))?;
+                self.is_configured = true;
+                if self.program.is_some() {
+                    self.begin(connection)?;
+                }
+            }
+            "threads" => {
+                let thread = json!({ "id": THREAD_ID, "name": "main" });
+                connection.respond(request, json!({ "threads": [thread] }))?;
+            }
+            "pause" => {
+                // The reader thread has already set the flag. If nothing
+                // was running, the flag must not stop the next evaluation.
+                self.interrupt.store(false, Ordering::SeqCst);
+                connection.respond(request, json!({}))?;
+            }
+            "disconnect" => {
+                connection.respond(request, json!({}))?;
+                return Ok(false);
+            }
+            "terminate" => {
+                self.interrupt.store(false, Ordering::SeqCst);
+                connection.respond(request, json!({}))?;
+                connection.event("terminated", json!({}))?;
+                self.program = None;
+            }
+            _ => match &self.program {
+                Some(_) => self.handle_running(request, connection)?,
+                None => connection.refuse(request, "No program launched")?,
+            },
+        }
+        Ok(true)
+    }
+
+    /// Handle a request that needs a launched program.
+    fn handle_running(
+        &mut self,
+        request: &Value,
+        connection: &mut Connection,
+    ) -> anyhow::Result<()> {
+        let arguments = &request["arguments"];
+        let is_instruction = arguments["granularity"] == "instruction";
+        let program = match &mut self.program {
+            Some(program) => program,
+            None => return Ok(()),
+        };
+        let machine = program.debugger.machine();
+        let command = request["command"].as_str().unwrap_or_default();
+        match command {
+            "stackTrace" => {
+                let lines = instruction_lines(&program.source);
+                let name = program.path.file_name();
+                let source = json!({
+                    "name": name.map(|name| name.to_string_lossy()),
+                    "path": program.path,
+                });
+                let stack = machine.call_stack().iter().enumerate().rev();
+                let frames: Vec<Value> = stack
+                    .map(|(id, evaluation)| {
+                        let (line, column) =
+                            match span_of(&evaluation.expression) {
+                                Some(span) if span.line > 0 => {
+                                    (span.line, span.column)
+                                }
+                                _ => lines
+                                    .iter()
+                                    .find(|(label, _, _)| {
+                                        *label == evaluation.address
+                                    })
+                                    .map(|(_, first, _)| (*first, 1))
+                                    .unwrap_or((0, 0)),
+                            };
+                        let name = format!(
+                            "{}: {}",
+                            evaluation.address, evaluation.expression
+                        );
+                        json!({
+                            "id": id,
+                            "name": truncate(&name),
+                            "source": source,
+                            "line": line,
+                            "column": column,
+                        })
+                    })
+                    .collect();
+                let body = json!({
+                    "stackFrames": frames,
+                    "totalFrames": frames.len(),
+                });
+                connection.respond(request, body)?;
+            }
+            "scopes" => {
+                let scopes = json!([
+                    {
+                        "name": "Call",
+                        "variablesReference": CALL_SCOPE,
+                        "expensive": false,
+                    },
+                    {
+                        "name": "Tape",
+                        "variablesReference": TAPE_SCOPE,
+                        "expensive": false,
+                    },
+                ]);
+                connection.respond(request, json!({ "scopes": scopes }))?;
+            }
+            "variables" => {
+                let reference = arguments["variablesReference"].as_u64();
+                let variables: Vec<Value> = match reference {
+                    Some(reference) if reference == CALL_SCOPE as u64 => vec![
+                        variable("argument", machine.tape().get(0)),
+                        variable("fetched", Some(machine.fetched())),
+                    ],
+                    Some(reference) if reference == TAPE_SCOPE as u64 => {
+                        machine
+                            .tape()
+                            .iter()
+                            .filter(|(address, _)| *address > 0)
+                            .map(|(address, expression)| {
+                                variable(&address.to_string(), Some(expression))
+                            })
+                            .collect()
+                    }
+                    _ => Vec::new(),
+                };
+                let body = json!({ "variables": variables });
+                connection.respond(request, body)?;
+            }
+            "evaluate" => {
+                // Only reading from the tape is supported, because evaluating
+                // an expression would disturb the evaluation in progress.
+                let expression = arguments["expression"].as_str();
+                let address = expression
+                    .map(|expression| expression.trim().trim_start_matches('*'))
+                    .and_then(|address| address.trim().parse::<usize>().ok());
+                match address {
+                    Some(address) => {
+                        let result = value_of(machine.tape().get(address));
+                        let body = json!({
+                            "result": result,
+                            "variablesReference": 0,
+                        });
+                        connection.respond(request, body)?;
+                    }
+                    None => connection.refuse(
+                        request,
+                        "Only addresses such as *5 can be evaluated",
+                    )?,
+                }
+            }
+            "continue" => {
+                let body = json!({ "allThreadsContinued": true });
+                connection.respond(request, body)?;
+                let stop = program.debugger.resume();
+                self.report(stop, connection)?;
+            }
+            "next" => {
+                connection.respond(request, json!({}))?;
+                let granularity = if is_instruction {
+                    Granularity::Tick
+                } else {
+                    Granularity::Statement
+                };
+                let stop = program.debugger.step(granularity);
+                self.report(stop, connection)?;
+            }
+            "stepIn" => {
+                connection.respond(request, json!({}))?;
+                let granularity = if is_instruction {
+                    Granularity::Tick
+                } else {
+                    Granularity::Call
+                };
+                let stop = program.debugger.step(granularity);
+                self.report(stop, connection)?;
+            }
+            _ => connection.refuse(request, "Unsupported request")?,
+        }
+        Ok(())
+    }
+
+    fn apply_breakpoints(&mut self) {
+        if let Some(program) = &mut self.program {
+            let debugger = &mut program.debugger;
+            let breakpoints: Vec<usize> = debugger.breakpoints().collect();
+            for address in breakpoints {
+                debugger.remove_breakpoint(address);
+            }
+            let watchpoints: Vec<usize> = debugger.watchpoints().collect();
+            for address in watchpoints {
+                debugger.remove_watchpoint(address);
+            }
+            for address in self.breakpoints.iter() {
+                debugger.add_breakpoint(*address);
+            }
+            for address in self.watchpoints.iter() {
+                debugger.add_watchpoint(*address);
+            }
+        }
+    }
+
+    /// Start evaluating the entry point, once the program is launched and
+    /// the client has sent its breakpoints.
+    fn begin(&mut self, connection: &mut Connection) -> anyhow::Result<()> {
+        let program = match &mut self.program {
+            Some(program) => program,
+            None => return Ok(()),
+        };
+        program.debugger.start(1);
+        let is_at_breakpoint = program.debugger.is_at_entry_breakpoint();
+        if self.is_stop_on_entry && !is_at_breakpoint {
+            stopped(connection, "entry", None)
+        } else {
+            let stop = program.debugger.resume();
+            self.report(stop, connection)
+        }
+    }
+
+    fn report(
+        &mut self,
+        stop: Stop,
+        connection: &mut Connection,
+    ) -> anyhow::Result<()> {
+        let printed = self.output.contents();
+        if !printed.is_empty() {
+            self.output.clear();
+            let body = json!({ "category": "stdout", "output": printed });
+            connection.event("output", body)?;
+        }
+        match stop {
+            Stop::Step => stopped(connection, "step", None),
+            Stop::Breakpoint { address } => {
+                let text = format!("Called {}", address);
+                stopped(connection, "breakpoint", Some(text))
+            }
+            Stop::Watchpoint { address } => {
+                let text = format!("Wrote to {}", address);
+                stopped(connection, "data breakpoint", Some(text))
+            }
+            Stop::Interrupted => stopped(connection, "pause", None),
+            Stop::Finished(result) => {
+                let (output, category, exit_code) = match result {
+                    Ok(output) => {
+                        (format!("Output: {}\n", output), "console", 0)
+                    }
+                    Err(e) => (format!("Runtime error: {}\n", e), "stderr", 1),
+                };
+                let body = json!({ "category": category, "output": output });
+                connection.event("output", body)?;
+                let body = json!({ "exitCode": exit_code });
+                connection.event("exited", body)?;
+                connection.event("terminated", json!({}))
+            }
+        }
+    }
+}
+
+fn stopped(
+    connection: &mut Connection,
+    reason: &str,
+    text: Option<String>,
+) -> anyhow::Result<()> {
+    let mut body = json!({
+        "reason": reason,
+        "threadId": THREAD_ID,
+        "allThreadsStopped": true,
+    });
+    if let Some(text) = text {
+        body["text"] = json!(text);
+    }
+    connection.event("stopped", body)
+}
+
+fn variable(name: &str, expression: Option<&Expression>) -> Value {
+    json!({
+        "name": name,
+        "value": value_of(expression),
+        "variablesReference": 0,
+    })
+}
+
+fn value_of(expression: Option<&Expression>) -> String {
+    match expression {
+        None | Some(Expression::Undefined) => "undefined".to_string(),
+        Some(expression) => deparse::deparse(expression),
+    }
+}
+
+fn truncate(text: &str) -> String {
+    const WIDTH: usize = 60;
+    if text.chars().count() > WIDTH {
+        let text: String = text.chars().take(WIDTH - 1).collect();
+        format!("{}…", text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// The label of every instruction, along with the first and last line that
+/// it spans.
+fn instruction_lines(source: &str) -> Vec<(usize, usize, usize)> {
+    lexer::instruction_spans(source)
+        .into_iter()
+        .map(|(label, span)| {
+            let newlines = source[span.start..span.end].matches('\n').count();
+            (label, span.line, span.line + newlines)
+        })
+        .collect()
+}
@@ -0,0 +1,199 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use numpad::common::*;
+use numpad::debugger::{span_of, Debugger, Granularity, Stop};
+use numpad::{deparse, Machine};
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+const DEBUG_COMMANDS: &str = "\
+break N     Pause when instruction N is called (b)
+delete N    Remove the breakpoint at N (d)
+watch N     Pause when address N or one of its elements is written to (w)
+unwatch N   Remove the watchpoint at N
+tick        Perform a single tick (t)
+step        Run until the next statement begins (s)
+call        Run until the next instruction is called
+continue    Run until a breakpoint or watchpoint is hit (c)
+stack       Show the expressions that are being evaluated (bt)
+fetched     Show the value that was produced most recently (f)
+print N     Show what is stored at address N (p)
+tape        List the defined addresses
+run [N]     Start evaluating address N, by default 1 (r)
+quit        Exit (q)
+help        Show this list of commands (h)";
+
+/// Step through the program interactively. The evaluation of address 1
+/// starts paused, before its first tick.
+pub fn debug(source: &str, machine: Machine) -> anyhow::Result<()> {
+    let initial = machine.tape().clone();
+    let mut debugger = Debugger::new(machine);
+    let interrupt = Arc::new(AtomicBool::new(false));
+    debugger.set_interrupt(interrupt.clone());
+    ctrlc::set_handler(move || {
+        interrupt.store(true, std::sync::atomic::Ordering::SeqCst);
+    })?;
+    debugger.start(1);
+    println!("Paused before evaluating 1, type help for a list of commands");
+
+    let mut rl = DefaultEditor::new()?;
+    let mut previous = String::new();
+    loop {
+        let line = match rl.readline("(debug) ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => Err(error)?,
+        };
+        // An empty line repeats the previous command, to keep stepping.
+        let line = match line.trim() {
+            "" => previous.clone(),
+            line => {
+                rl.add_history_entry(line)?;
+                line.to_string()
+            }
+        };
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let address = words.next().and_then(|word| word.parse::<usize>().ok());
+        previous = line.clone();
+        match (command, address) {
+            ("" | "help" | "h", _) => println!("{}", DEBUG_COMMANDS),
+            ("quit" | "q", _) => break,
+            ("break" | "b", Some(address)) => {
+                debugger.add_breakpoint(address);
+                println!("Breakpoint at {}", address);
+            }
+            ("delete" | "d", Some(address)) => {
+                if debugger.remove_breakpoint(address) {
+                    println!("Removed the breakpoint at {}", address);
+                } else {
+                    println!("No breakpoint at {}", address);
+                }
+            }
+            ("watch" | "w", Some(address)) => {
+                debugger.add_watchpoint(address);
+                println!("Watching {}", address);
+            }
+            ("unwatch", Some(address)) => {
+                if debugger.remove_watchpoint(address) {
+                    println!("Stopped watching {}", address);
+                } else {
+                    println!("Not watching {}", address);
+                }
+            }
+            ("print" | "p", Some(address)) => {
+                println!("{}", describe(&debugger, address));
+            }
+            ("tape", _) => {
+                let addresses: Vec<String> = debugger
+                    .machine()
+                    .tape()
+                    .iter()
+                    .map(|(address, _)| address.to_string())
+                    .collect();
+                println!("Defined: {}", addresses.join(" "));
+            }
+            ("fetched" | "f", _) => {
+                println!("{}", debugger.machine().fetched());
+            }
+            ("stack" | "bt", _) => {
+                let stack = debugger.machine().call_stack().iter().rev();
+                for (depth, evaluation) in stack.enumerate() {
+                    println!(
+                        "#{} in {}: {}",
+                        depth, evaluation.address, evaluation.expression
+                    );
+                }
+            }
+            ("run" | "r", address) => {
+                let address = address.unwrap_or(1);
+                debugger.machine_mut().restore(initial.clone());
+                debugger.start(address);
+                println!("Paused before evaluating {}", address);
+            }
+            ("tick" | "t" | "step" | "s" | "call" | "continue" | "c", _)
+                if !debugger.is_running() =>
+            {
+                println!("Not running, type run to start again");
+            }
+            ("tick" | "t", _) => {
+                let stop = debugger.step(Granularity::Tick);
+                report(source, &debugger, stop);
+            }
+            ("step" | "s", _) => {
+                let stop = debugger.step(Granularity::Statement);
+                report(source, &debugger, stop);
+            }
+            ("call", _) => {
+                let stop = debugger.step(Granularity::Call);
+                report(source, &debugger, stop);
+            }
+            ("continue" | "c", _) => {
+                let stop = debugger.resume();
+                report(source, &debugger, stop);
+            }
+            (
+                "break" | "b" | "delete" | "d" | "watch" | "w" | "unwatch"
+                | "print" | "p",
+                None,
+            ) => println!("Expected an address"),
+            _ => println!("Unknown command, type help for a list"),
+        }
+    }
+    Ok(())
+}
+
+fn report(source: &str, debugger: &Debugger, stop: Stop) {
+    match stop {
+        Stop::Step => (),
+        Stop::Breakpoint { address } => println!("Breakpoint at {}", address),
+        Stop::Watchpoint { address } => {
+            println!("{}", describe(debugger, address))
+        }
+        Stop::Interrupted => println!("Interrupted"),
+        Stop::Finished(Ok(output)) => {
+            println!("Output: {}", output);
+            return;
+        }
+        Stop::Finished(Err(e)) => {
+            println!("Runtime error\nError :: {e}");
+            return;
+        }
+    }
+    let evaluation = match debugger.machine().call_stack().last() {
+        Some(evaluation) => evaluation,
+        None => return,
+    };
+    let address = evaluation.address;
+    match span_of(&evaluation.expression) {
+        Some(span) if span.line > 0 => {
+            let line = source.lines().nth(span.line - 1).unwrap_or_default();
+            println!("In {} at {}", address, span);
+            println!("{:>4} | {}", span.line, line);
+        }
+        _ => println!("In {}", address),
+    }
+    println!("     > {}", evaluation.expression);
+}
+
+fn describe(debugger: &Debugger, address: usize) -> String {
+    match debugger.machine().tape().get(address) {
+        Some(expression) => {
+            let instruction = Instruction {
+                label: address,
+                expression: expression.clone(),
+                span: Span::default(),
+            };
+            deparse::deparse_instruction(&instruction)
+        }
+        None => format!("{} is undefined", address),
+    }
+}
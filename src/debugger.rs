@@ -0,0 +1,240 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use crate::common::*;
+use crate::machine::{Event, Machine, RuntimeError};
+
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Runs a machine step by step, pausing at breakpoints and watchpoints.
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    interrupt: Option<Arc<AtomicBool>>,
+    is_running: bool,
+    /// A breakpoint on the instruction that was started, which is reported
+    /// before the first tick.
+    entry_breakpoint: Option<usize>,
+}
+
+/// How far to go before pausing again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    /// A single tick of the machine.
+    Tick,
+    /// Until the next statement of a sequence begins, or an instruction is
+    /// called.
+    Statement,
+    /// Until the next instruction is called.
+    Call,
+}
+
+/// Why the debugger paused.
+#[derive(Debug)]
+pub enum Stop {
+    Step,
+    /// The instruction at this address is about to be evaluated.
+    Breakpoint {
+        address: usize,
+    },
+    /// This address was just written to.
+    Watchpoint {
+        address: usize,
+    },
+    Interrupted,
+    Finished(Result<Expression, RuntimeError>),
+}
+
+impl Debugger {
+    pub fn new(machine: Machine) -> Debugger {
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            interrupt: None,
+            is_running: false,
+            entry_breakpoint: None,
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Pause as soon as the flag is set, for example by a Ctrl-C handler.
+    pub fn set_interrupt(&mut self, interrupt: Arc<AtomicBool>) {
+        self.interrupt = Some(interrupt);
+    }
+
+    /// Pause whenever the instruction at this address is called. Returns
+    /// false if there already was a breakpoint.
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Pause whenever this address, or an element of the list stored
+    /// there, is written to. Returns false if it was already watched.
+    pub fn add_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.insert(address)
+    }
+
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address)
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.watchpoints.iter().copied()
+    }
+
+    /// Begin evaluating the given address, paused before the first tick.
+    /// An evaluation that is still running is abandoned.
+    pub fn start(&mut self, address: usize) {
+        if self.is_running {
+            let _ = self.machine.finish();
+        }
+        self.machine.start(address);
+        self.is_running = true;
+        self.entry_breakpoint =
+            self.breakpoints.contains(&address).then_some(address);
+    }
+
+    /// Whether the evaluation that was just started has a breakpoint on its
+    /// first instruction, which the next step or resume stops at.
+    pub fn is_at_entry_breakpoint(&self) -> bool {
+        self.entry_breakpoint.is_some()
+    }
+
+    /// Whether an evaluation was started and has not finished yet.
+    pub fn is_running(&self) -> bool {
+        self.is_running
+    }
+
+    /// Run until the next step of the given granularity, or until a
+    /// breakpoint or watchpoint is hit.
+    pub fn step(&mut self, granularity: Granularity) -> Stop {
+        self.run_until(Some(granularity))
+    }
+
+    /// Run until a breakpoint or watchpoint is hit.
+    pub fn resume(&mut self) -> Stop {
+        self.run_until(None)
+    }
+
+    fn run_until(&mut self, granularity: Option<Granularity>) -> Stop {
+        if let Some(address) = self.entry_breakpoint.take() {
+            return Stop::Breakpoint { address };
+        }
+        loop {
+            if self.machine.is_finished() {
+                self.is_running = false;
+                return Stop::Finished(self.machine.finish());
+            }
+            if let Some(interrupt) = &self.interrupt {
+                if interrupt.swap(false, Ordering::SeqCst) {
+                    return Stop::Interrupted;
+                }
+            }
+            let event = self.machine.step();
+            match event {
+                Some(Event::Call { address })
+                    if self.breakpoints.contains(&address) =>
+                {
+                    return Stop::Breakpoint { address };
+                }
+                Some(Event::Write { address })
+                    if self.watchpoints.contains(&address) =>
+                {
+                    return Stop::Watchpoint { address };
+                }
+                _ => (),
+            }
+            if self.machine.is_finished() {
+                continue;
+            }
+            let is_call = matches!(event, Some(Event::Call { .. }));
+            match granularity {
+                Some(Granularity::Tick) => return Stop::Step,
+                Some(Granularity::Statement)
+                    if is_call || self.is_at_statement() =>
+                {
+                    return Stop::Step;
+                }
+                Some(Granularity::Call) if is_call => return Stop::Step,
+                _ => (),
+            }
+        }
+    }
+
+    /// Whether the next tick begins a statement of a sequence.
+    fn is_at_statement(&self) -> bool {
+        match self.machine.call_stack().last() {
+            Some(evaluation) => match &evaluation.expression {
+                Expression::Sequence(statements) => !statements.is_empty(),
+                _ => false,
+            },
+            None => false,
+        }
+    }
+}
+
+/// The location of the first operator in the expression, if it has one.
+pub fn span_of(expression: &Expression) -> Option<Span> {
+    match expression {
+        Expression::Unary { span, .. } | Expression::Binary { span, .. } => {
+            Some(*span)
+        }
+        Expression::Sequence(statements) => statements.iter().find_map(span_of),
+        Expression::List(elements) => elements.iter().find_map(span_of),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debugger(source: &str) -> Debugger {
+        Debugger::new(Machine::create(crate::compile(source).unwrap()))
+    }
+
+    #[test]
+    fn breakpoint_on_the_entry_instruction() {
+        let mut debugger = debugger("1 .. 2/3\n2 .. 1 + *0");
+        debugger.add_breakpoint(1);
+        debugger.add_breakpoint(2);
+        debugger.start(1);
+        assert!(debugger.is_at_entry_breakpoint());
+        assert!(matches!(debugger.resume(), Stop::Breakpoint { address: 1 }));
+        assert!(matches!(debugger.resume(), Stop::Breakpoint { address: 2 }));
+        match debugger.resume() {
+            Stop::Finished(Ok(output)) => assert_eq!(output.to_string(), "(4)"),
+            stop => panic!("unexpected {:?}", stop),
+        }
+    }
+
+    #[test]
+    fn no_breakpoint_on_the_entry_instruction() {
+        let mut debugger = debugger("1 .. 2/3\n2 .. 1 + *0");
+        debugger.start(1);
+        assert!(!debugger.is_at_entry_breakpoint());
+        assert!(matches!(debugger.resume(), Stop::Finished(Ok(_))));
+    }
+}
@@ -0,0 +1,294 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use crate::common::*;
+
+/// There is no literal for undefined, and `*0` is the call argument when it
+/// is evaluated lazily as an element of a list. Instead, undefined is written
+/// as a fetch from the highest address that can be fetched on any platform,
+/// which no program is expected to write to.
+pub const UNDEFINED_ADDRESS: u32 = u32::MAX;
+
+/// Render an expression as source code that evaluates to the same value.
+///
+/// Expressions that have no syntax of their own are written as something
+/// that evaluates to them: negative numbers are negated, infinity is the
+/// reciprocal of zero, a pointer into a list fetches the list and drops the
+/// first elements, and undefined is a fetch from [`UNDEFINED_ADDRESS`].
+pub fn deparse(expression: &Expression) -> String {
+    let mut output = String::new();
+    write_expression(expression, false, &mut output);
+    output
+}
+
+/// Render an instruction in the layout used by `numpad fmt`, with each
+/// statement of a sequence on its own line.
+pub fn deparse_instruction(instruction: &Instruction) -> String {
+    let mut output = instruction.label.to_string();
+    match &instruction.expression {
+        Expression::Sequence(statements) if statements.len() > 1 => {
+            for statement in statements {
+                output.push_str("\n.. ");
+                write_expression(statement, false, &mut output);
+            }
+        }
+        expression => {
+            output.push_str(" .. ");
+            write_expression(expression, false, &mut output);
+        }
+    }
+    output
+}
+
+/// Unary operators apply to everything to their right and binary operators
+/// group from right to left, so only the left operand of a binary operator
+/// ever needs to be nested.
+pub(crate) fn write_expression(
+    expression: &Expression,
+    is_compact: bool,
+    output: &mut String,
+) {
+    match expression {
+        Expression::Undefined | Expression::Stub => {
+            output.push('*');
+            output.push_str(&UNDEFINED_ADDRESS.to_string());
+        }
+        Expression::Number(number) => write_number(*number, output),
+        Expression::List(elements) => {
+            output.push_str("/.");
+            if elements.len() == 1 {
+                output.push_str("..");
+            }
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    output.push_str("..");
+                }
+                write_expression(element, is_compact, output);
+            }
+            output.push_str("./");
+        }
+        Expression::Sequence(statements) => {
+            let separator = if is_compact { ".." } else { " .. " };
+            for (i, statement) in statements.iter().enumerate() {
+                if i > 0 {
+                    output.push_str(separator);
+                }
+                write_expression(statement, is_compact, output);
+            }
+        }
+        Expression::Unary {
+            operator, operand, ..
+        } => {
+            output.push_str(unary_symbol(*operator));
+            write_expression(operand, is_compact, output);
+        }
+        Expression::Binary {
+            operator,
+            left,
+            right,
+            ..
+        } => {
+            if is_atom(left) {
+                write_expression(left, is_compact, output);
+            } else {
+                output.push_str("/.");
+                write_expression(&end_with_value(left), is_compact, output);
+                output.push_str("./");
+            }
+            if is_compact {
+                output.push_str(binary_symbol(*operator));
+            } else {
+                output.push(' ');
+                output.push_str(binary_symbol(*operator));
+                output.push(' ');
+            }
+            write_expression(right, is_compact, output);
+        }
+        Expression::PointerIntoList { address, offset } => {
+            if *offset > 0 {
+                output.push_str(&offset.to_string());
+                output.push_str(if is_compact { "+" } else { " + " });
+            }
+            output.push('*');
+            output.push_str(&address.to_string());
+        }
+    }
+}
+
+fn write_number(number: Float, output: &mut String) {
+    if number.is_nan() {
+        output.push_str("0*/0");
+    } else if number.is_sign_negative() {
+        output.push('-');
+        write_number(-number, output);
+    } else if number.is_infinite() {
+        output.push_str("/0");
+    } else {
+        let text = number.to_string();
+        output.push_str(&text);
+        // Integers that are too large for a label are only valid as floats.
+        if !text.contains('.') && text.parse::<Integral>().is_err() {
+            output.push_str(".0");
+        }
+    }
+}
+
+/// Whether the expression can be the left operand of a binary operator
+/// without being nested.
+fn is_atom(expression: &Expression) -> bool {
+    match expression {
+        Expression::Number(number) => {
+            number.is_finite() && number.is_sign_positive()
+        }
+        Expression::List(_) => ends_with_value(expression),
+        _ => false,
+    }
+}
+
+/// Whether the source code of the expression ends in a value rather than a
+/// separator, which is needed for an operator after it to be binary.
+fn ends_with_value(expression: &Expression) -> bool {
+    match expression {
+        Expression::List(elements) => {
+            elements.last().is_some_and(ends_with_value)
+        }
+        Expression::Sequence(statements) => {
+            statements.back().is_some_and(ends_with_value)
+        }
+        Expression::Unary { operand, .. } => ends_with_value(operand),
+        Expression::Binary { right, .. } => ends_with_value(right),
+        _ => true,
+    }
+}
+
+/// An equivalent expression that ends in a value, by writing an empty list
+/// at the end as `1 + /...0./`, which drops the only element of a list.
+fn end_with_value(expression: &Expression) -> Expression {
+    match expression {
+        Expression::List(elements) => match elements.split_last() {
+            Some((last, elements)) => {
+                let mut elements = elements.to_vec();
+                elements.push(end_with_value(last));
+                Expression::List(elements)
+            }
+            None => Expression::Binary {
+                operator: Binary::Plus,
+                left: Box::new(Expression::Number(1.0)),
+                right: Box::new(Expression::List(vec![Expression::Number(
+                    0.0,
+                )])),
+                span: Span::default(),
+            },
+        },
+        Expression::Sequence(statements) => {
+            let mut statements = statements.clone();
+            if let Some(last) = statements.pop_back() {
+                statements.push_back(end_with_value(&last));
+            }
+            Expression::Sequence(statements)
+        }
+        Expression::Unary {
+            operator,
+            operand,
+            span,
+        } => Expression::Unary {
+            operator: *operator,
+            operand: Box::new(end_with_value(operand)),
+            span: *span,
+        },
+        Expression::Binary {
+            operator,
+            left,
+            right,
+            span,
+        } => Expression::Binary {
+            operator: *operator,
+            left: left.clone(),
+            right: Box::new(end_with_value(right)),
+            span: *span,
+        },
+        expression => expression.clone(),
+    }
+}
+
+fn unary_symbol(operator: Unary) -> &'static str {
+    match operator {
+        Unary::Fetch => "*",
+        Unary::Signum => "+",
+        Unary::Neg => "-",
+        Unary::Recip => "/",
+        Unary::Ceiling => "+.",
+        Unary::Floor => "-.",
+        Unary::Print => "*.",
+    }
+}
+
+fn binary_symbol(operator: Binary) -> &'static str {
+    match operator {
+        Binary::Plus => "+",
+        Binary::Mult => "*",
+        Binary::Assign => "-",
+        Binary::CallWith => "/",
+        Binary::Abort => "*.",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Machine;
+
+    fn evaluate(source: &str) -> Expression {
+        let mut machine = Machine::create(crate::compile(source).unwrap());
+        machine.evaluate(1).unwrap()
+    }
+
+    #[test]
+    fn undefined_element_stays_undefined_in_a_call() {
+        let list = Expression::List(vec![
+            Expression::Number(1.0),
+            Expression::Undefined,
+        ]);
+        let source = format!("1 .. 2/7\n2 .. */.1 + {}./", deparse(&list));
+        assert!(matches!(evaluate(&source), Expression::Undefined));
+    }
+
+    #[test]
+    fn empty_list_as_left_operand() {
+        let expression = Expression::Binary {
+            operator: Binary::Assign,
+            left: Box::new(Expression::List(Vec::new())),
+            right: Box::new(Expression::Number(5.0)),
+            span: Span::default(),
+        };
+        let source = format!("1 .. {}", deparse(&expression));
+        match evaluate(&source) {
+            Expression::List(elements) => {
+                assert!(
+                    matches!(elements[..], [Expression::Number(n)] if n == 5.0)
+                )
+            }
+            output => panic!("Expected a list, got {}", output),
+        }
+    }
+
+    #[test]
+    fn deparse_is_stable() {
+        let sources = [
+            "1 .. -5 + /.1..2./ * 3",
+            "1 .. /.-5./ + 1",
+            "1 .. /.../../ ./",
+            "1 .. /0 .. 0*/0 .. 2 + *3",
+        ];
+        for source in sources {
+            for instruction in crate::compile(source).unwrap() {
+                let text = deparse_instruction(&instruction);
+                let reparsed = crate::compile(&text).unwrap();
+                assert_eq!(deparse_instruction(&reparsed[0]), text);
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use crate::common::*;
+
+use std::fmt::Write;
+
+/// An error in the source code, with a stable error code and an optional
+/// hint on how to fix it.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    pub hint: Option<&'static str>,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error[{}]: {} at {}", self.code, self.message, self.span)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+impl Diagnostic {
+    /// Render the diagnostic in the style of rustc, quoting the offending
+    /// line of source code and underlining the span.
+    pub fn render(&self, source: &str, path: Option<&str>) -> String {
+        self.render_as("error", source, path)
+    }
+
+    /// Render the diagnostic like [`Diagnostic::render`], but as a warning
+    /// that does not stop compilation.
+    pub fn render_warning(&self, source: &str, path: Option<&str>) -> String {
+        self.render_as("warning", source, path)
+    }
+
+    fn render_as(
+        &self,
+        severity: &str,
+        source: &str,
+        path: Option<&str>,
+    ) -> String {
+        let mut output = String::new();
+        let line_number = self.span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        let line = source.lines().nth(self.span.line.saturating_sub(1));
+        writeln!(output, "{severity}[{}]: {}", self.code, self.message)
+            .unwrap();
+        match path {
+            Some(path) => {
+                writeln!(output, "{gutter}--> {path}:{}", self.span).unwrap()
+            }
+            None => writeln!(output, "{gutter}--> {}", self.span).unwrap(),
+        }
+        if let Some(line) = line {
+            let column = self.span.column.saturating_sub(1);
+            let padding: String = line
+                .chars()
+                .take(column)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let width = source
+                .get(self.span.start..self.span.end)
+                .and_then(|text| text.lines().next())
+                .map(|text| text.chars().count())
+                .unwrap_or_default()
+                .max(1);
+            let carets = "^".repeat(width);
+            writeln!(output, "{gutter} |").unwrap();
+            writeln!(output, "{line_number} | {line}").unwrap();
+            writeln!(output, "{gutter} | {padding}{carets}").unwrap();
+        }
+        if let Some(hint) = self.hint {
+            writeln!(output, "{gutter} = hint: {hint}").unwrap();
+        }
+        output
+    }
+}
@@ -0,0 +1,214 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use crate::common::*;
+use crate::diagnostic::Diagnostic;
+use crate::lexer;
+use crate::lexer::LabelPass1;
+use crate::lexer::TokenTreePass1;
+use crate::parser;
+
+/// Format source code in the canonical layout. Instructions with a single
+/// statement are kept on one line, unless the label has a comment of its own
+/// and the statement is on a later line. Otherwise the label is followed by one
+/// statement per line. Comments and blank lines between instructions are
+/// preserved.
+pub fn format(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let (labels, mut diagnostics) = lexer::lex_all(source);
+    let (_, parse_diagnostics) = parser::parse_all(labels.clone());
+    diagnostics.extend(parse_diagnostics);
+    if !diagnostics.is_empty() {
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        return Err(diagnostics);
+    }
+
+    let comments = lexer::comments(source);
+    let mut lines = Vec::new();
+    for (index, LabelPass1(tokens)) in labels.iter().enumerate() {
+        if !tokens.is_empty() {
+            layout_instruction(source, tokens, &comments, index, &mut lines);
+        }
+    }
+    for span in comments {
+        let comment = source[span.start..span.end].to_string();
+        let covering = lines.iter_mut().rev().find(|line| {
+            line.instruction.is_some()
+                && line.first_line <= span.line
+                && span.line <= line.last_line
+        });
+        match covering {
+            Some(line) => line.comments.push(comment),
+            None => {
+                let position = lines
+                    .iter()
+                    .position(|line| line.first_line > span.line)
+                    .unwrap_or(lines.len());
+                let line = Line {
+                    code: String::new(),
+                    comments: vec![comment],
+                    first_line: span.line,
+                    last_line: span.line,
+                    instruction: None,
+                    is_label: false,
+                };
+                lines.insert(position, line);
+            }
+        }
+    }
+
+    let mut output = String::new();
+    let mut previous: Option<&Line> = None;
+    for line in lines.iter() {
+        if let Some(previous) = previous {
+            let is_separated = line.first_line > previous.last_line + 1;
+            let can_separate = line.is_label || line.instruction.is_none();
+            if is_separated && can_separate {
+                output.push('\n');
+            }
+        }
+        output.push_str(&line.code);
+        if !line.comments.is_empty() {
+            if line.instruction.is_some() {
+                let width = lines
+                    .iter()
+                    .filter(|other| other.instruction == line.instruction)
+                    .filter(|other| !other.comments.is_empty())
+                    .map(|other| other.code.chars().count())
+                    .max()
+                    .unwrap_or_default();
+                let padding = width - line.code.chars().count() + 2;
+                output.push_str(&" ".repeat(padding));
+            }
+            output.push_str(&line.comments.join(" "));
+        }
+        output.push('\n');
+        previous = Some(line);
+    }
+    Ok(output)
+}
+
+struct Line {
+    code: String,
+    comments: Vec<String>,
+    first_line: usize,
+    last_line: usize,
+    instruction: Option<usize>,
+    is_label: bool,
+}
+
+fn layout_instruction(
+    source: &str,
+    tokens: &[Spanned<TokenTreePass1>],
+    comments: &[Span],
+    index: usize,
+    lines: &mut Vec<Line>,
+) {
+    let label = &tokens[0];
+    let statements: Vec<&[Spanned<TokenTreePass1>]> = tokens[1..]
+        .split(|token| matches!(token.value, TokenTreePass1::Sep))
+        .skip(1)
+        .collect();
+    let first_line = label.span.line;
+    let last_line = last_line_of(source, tokens);
+    let label = text_of(source, label.span);
+    let is_heading = |statement: &[Spanned<TokenTreePass1>]| {
+        statement[0].span.line > first_line
+            && comments.iter().any(|comment| comment.line == first_line)
+    };
+    match statements.as_slice() {
+        [statement] if !is_heading(statement) => {
+            lines.push(Line {
+                code: format!("{} .. {}", label, render(source, statement)),
+                comments: Vec::new(),
+                first_line,
+                last_line,
+                instruction: Some(index),
+                is_label: true,
+            });
+        }
+        _ => {
+            lines.push(Line {
+                code: label,
+                comments: Vec::new(),
+                first_line,
+                last_line: first_line,
+                instruction: Some(index),
+                is_label: true,
+            });
+            for statement in statements {
+                lines.push(Line {
+                    code: format!(".. {}", render(source, statement)),
+                    comments: Vec::new(),
+                    first_line: statement[0].span.line,
+                    last_line: last_line_of(source, statement),
+                    instruction: Some(index),
+                    is_label: false,
+                });
+            }
+        }
+    }
+}
+
+fn render(source: &str, tokens: &[Spanned<TokenTreePass1>]) -> String {
+    let mut output = String::new();
+    for token in tokens {
+        match &token.value {
+            TokenTreePass1::Int(_)
+            | TokenTreePass1::Float(_)
+            | TokenTreePass1::Unary(_) => {
+                output.push_str(&text_of(source, token.span));
+            }
+            TokenTreePass1::Binary(_) => {
+                output.push(' ');
+                output.push_str(&text_of(source, token.span));
+                output.push(' ');
+            }
+            TokenTreePass1::NestExpr(inner) => {
+                output.push_str("/.");
+                output.push_str(&render(source, inner));
+                output.push_str("./");
+            }
+            TokenTreePass1::Sep => output.push_str(".."),
+        }
+    }
+    output
+}
+
+fn text_of(source: &str, span: Span) -> String {
+    source[span.start..span.end].split_whitespace().collect()
+}
+
+fn last_line_of(source: &str, tokens: &[Spanned<TokenTreePass1>]) -> usize {
+    let first = tokens.first().map(|token| token.span).unwrap_or_default();
+    let last = tokens.last().map(|token| token.span).unwrap_or_default();
+    let newlines = source[first.start..last.end].matches('\n').count();
+    first.line + newlines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_and_blank_lines_are_kept() {
+        let source = "(header)\n\n\
+                      1 .. 2/3 (call)\n\
+                      2 (doubles)\n.. *0 + *0\n\n\n\
+                      3 .. 7 (unused)\n";
+        let expected = "(header)\n\n\
+                        1 .. 2 / 3  (call)\n\
+                        2  (doubles)\n.. *0 + *0\n\n\
+                        3 .. 7  (unused)\n";
+        assert_eq!(format(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn syntax_errors_are_reported() {
+        let diagnostics = format("1 .. /.2 +").unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.column, 6);
+    }
+}
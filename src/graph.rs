@@ -0,0 +1,305 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use crate::common::*;
+
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Which instruction uses which address, and how.
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    /// The labels of all instructions.
+    pub instructions: BTreeSet<Integral>,
+    pub edges: BTreeSet<Edge>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    /// The label of the instruction that contains the operation.
+    pub from: Integral,
+    pub to: Target,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    /// A fetch such as `*30`.
+    Fetch,
+    /// A call such as `40/...`.
+    Call,
+    /// An assignment such as `50-...`.
+    Assign,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Target {
+    /// A literal address.
+    Address(Integral),
+    /// A fetch of a literal `-1` or `-2`, which reads input.
+    Input,
+    /// An address that is only known while the program runs, such as in
+    /// `*/.29+1./`.
+    Dynamic,
+}
+
+impl EdgeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Fetch => "fetch",
+            EdgeKind::Call => "call",
+            EdgeKind::Assign => "assign",
+        }
+    }
+}
+
+/// Collect the edges of every instruction. As in [`crate::Machine::create`],
+/// an instruction replaces any earlier one with the same label.
+pub fn graph(instructions: &[Instruction]) -> Graph {
+    let by_label: BTreeMap<Integral, &Expression> = instructions
+        .iter()
+        .map(|instruction| (instruction.label, &instruction.expression))
+        .collect();
+    let mut graph = Graph::default();
+    for (label, expression) in by_label {
+        graph.instructions.insert(label);
+        collect_edges(expression, label, &mut graph.edges);
+    }
+    graph
+}
+
+fn collect_edges(
+    expression: &Expression,
+    from: Integral,
+    edges: &mut BTreeSet<Edge>,
+) {
+    match expression {
+        Expression::List(elements) => elements
+            .iter()
+            .for_each(|element| collect_edges(element, from, edges)),
+        Expression::Sequence(elements) => elements
+            .iter()
+            .for_each(|element| collect_edges(element, from, edges)),
+        Expression::Unary {
+            operator, operand, ..
+        } => {
+            if let Unary::Fetch = operator {
+                // An instruction that fetches its own address reads the
+                // argument it was called with, as in `9000 .. 50 + *9000`.
+                let to = match target(operand, EdgeKind::Fetch) {
+                    Target::Address(address) if address == from => {
+                        Target::Address(0)
+                    }
+                    to => to,
+                };
+                let kind = EdgeKind::Fetch;
+                edges.insert(Edge { from, to, kind });
+            }
+            collect_edges(operand, from, edges);
+        }
+        Expression::Binary {
+            operator,
+            left,
+            right,
+            ..
+        } => {
+            let kind = match operator {
+                Binary::CallWith => Some(EdgeKind::Call),
+                Binary::Assign => Some(EdgeKind::Assign),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                let to = target(left, kind);
+                edges.insert(Edge { from, to, kind });
+            }
+            collect_edges(left, from, edges);
+            collect_edges(right, from, edges);
+        }
+        _ => (),
+    }
+}
+
+/// Only a fetch reads input. Calls and assignments clamp negative addresses
+/// to 0, like any other address below one half.
+fn target(operand: &Expression, kind: EdgeKind) -> Target {
+    let number = match operand {
+        Expression::Number(number) => *number,
+        Expression::Unary {
+            operator: Unary::Neg,
+            operand,
+            ..
+        } => match operand.as_ref() {
+            Expression::Number(number) => -number,
+            _ => return Target::Dynamic,
+        },
+        _ => return Target::Dynamic,
+    };
+    if kind == EdgeKind::Fetch && crate::machine::is_input_address(number) {
+        Target::Input
+    } else if number < 0.5 {
+        Target::Address(0)
+    } else {
+        Target::Address(number as Integral)
+    }
+}
+
+impl Graph {
+    /// Addresses that are used but are not instructions.
+    pub fn data(&self) -> BTreeSet<Integral> {
+        self.edges
+            .iter()
+            .filter_map(|edge| match edge.to {
+                Target::Address(address) => Some(address),
+                _ => None,
+            })
+            .filter(|address| !self.instructions.contains(address))
+            .collect()
+    }
+
+    /// Render the graph in the DOT language of Graphviz. Instructions are
+    /// boxes and other addresses are ellipses. Edges with a dynamic target are
+    /// red and lead to a `?` node of their own for each instruction.
+    pub fn to_dot(&self) -> String {
+        let mut output = String::new();
+        writeln!(output, "digraph numpad {{").unwrap();
+        writeln!(output, "    node [shape=box];").unwrap();
+        for label in &self.instructions {
+            writeln!(output, "    \"{label}\";").unwrap();
+        }
+        for address in self.data() {
+            writeln!(output, "    \"{address}\" [shape=ellipse];").unwrap();
+        }
+        let mut is_input_used = false;
+        let mut dynamic = BTreeSet::new();
+        for edge in &self.edges {
+            match edge.to {
+                Target::Input => is_input_used = true,
+                Target::Dynamic => {
+                    dynamic.insert(edge.from);
+                }
+                Target::Address(_) => (),
+            }
+        }
+        if is_input_used {
+            writeln!(output, "    \"input\" [shape=plaintext];").unwrap();
+        }
+        for from in dynamic {
+            writeln!(
+                output,
+                "    \"?{from}\" [label=\"?\", shape=diamond, color=red];"
+            )
+            .unwrap();
+        }
+        for edge in &self.edges {
+            let (to, color) = match edge.to {
+                Target::Address(address) => (address.to_string(), "black"),
+                Target::Input => ("input".to_string(), "black"),
+                Target::Dynamic => (format!("?{}", edge.from), "red"),
+            };
+            let style = match edge.kind {
+                EdgeKind::Fetch => "solid",
+                EdgeKind::Call => "bold",
+                EdgeKind::Assign => "dashed",
+            };
+            writeln!(
+                output,
+                "    \"{}\" -> \"{}\" [label={}, style={}, color={}];",
+                edge.from,
+                to,
+                edge.kind.name(),
+                style,
+                color
+            )
+            .unwrap();
+        }
+        writeln!(output, "}}").unwrap();
+        output
+    }
+
+    /// Render the graph as JSON, with a list of nodes and a list of edges.
+    /// The target of an edge is an address, `"input"` or `null` if it is
+    /// dynamic.
+    pub fn to_json(&self) -> Value {
+        let instructions = self
+            .instructions
+            .iter()
+            .map(|label| json!({ "address": label, "kind": "instruction" }));
+        let data = self
+            .data()
+            .into_iter()
+            .map(|address| json!({ "address": address, "kind": "data" }));
+        let nodes: Vec<Value> = instructions.chain(data).collect();
+        let edges: Vec<Value> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                let to = match edge.to {
+                    Target::Address(address) => json!(address),
+                    Target::Input => json!("input"),
+                    Target::Dynamic => Value::Null,
+                };
+                json!({
+                    "from": edge.from,
+                    "to": to,
+                    "kind": edge.kind.name(),
+                    "dynamic": edge.to == Target::Dynamic,
+                })
+            })
+            .collect();
+        json!({ "nodes": nodes, "edges": edges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edges(source: &str) -> Vec<(Integral, Target, EdgeKind)> {
+        graph(&crate::compile(source).unwrap())
+            .edges
+            .into_iter()
+            .map(|edge| (edge.from, edge.to, edge.kind))
+            .collect()
+    }
+
+    #[test]
+    fn fetch_of_own_address_reads_the_argument() {
+        let edges = edges("1 .. 9000/4\n9000 .. 50 + *9000");
+        assert_eq!(
+            edges,
+            [
+                (1, Target::Address(9000), EdgeKind::Call),
+                (9000, Target::Address(0), EdgeKind::Fetch),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_fetches_read_input() {
+        let edges = edges("1 .. /.-1./ - 5 .. /.-2./ / 3 .. *-1");
+        assert_eq!(
+            edges,
+            [
+                (1, Target::Address(0), EdgeKind::Call),
+                (1, Target::Address(0), EdgeKind::Assign),
+                (1, Target::Input, EdgeKind::Fetch),
+            ]
+        );
+    }
+
+    #[test]
+    fn call_of_own_address_is_recursion() {
+        let edges = edges("9000 .. 9000/*0");
+        assert_eq!(
+            edges,
+            [
+                (9000, Target::Address(0), EdgeKind::Fetch),
+                (9000, Target::Address(9000), EdgeKind::Call),
+            ]
+        );
+    }
+}
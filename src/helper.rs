@@ -0,0 +1,175 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use numpad::lexer::{self, Token};
+use numpad::{deparse, Tape};
+
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use std::borrow::Cow;
+
+const HINT_WIDTH: usize = 40;
+
+/// Highlights, validates and hints REPL input, using the tokens of the real
+/// lexer so that the REPL never disagrees with the language.
+#[derive(Default)]
+pub struct ReplHelper {
+    /// A copy of the tape, for showing what is stored at an address.
+    pub tape: Tape,
+    /// The lines of the current submission that were already entered.
+    pub pending: String,
+}
+
+impl rustyline::Helper for ReplHelper {}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+}
+
+/// Colours in the spirit of `support/Numpad.sublime-syntax`.
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if line.starts_with("---") {
+            return Cow::Borrowed(line);
+        }
+        let tokens: Vec<(Token, &str)> = lexer::tokens(line)
+            .map(|(token, _, slice)| (token, slice))
+            .collect();
+        let mut output = String::new();
+        let mut is_line_start = true;
+        for (i, (token, slice)) in tokens.iter().enumerate() {
+            let previous = i.checked_sub(1).map(|i| tokens[i].0);
+            let next = tokens.get(i + 1).map(|(token, _)| *token);
+            let colour = match token {
+                Token::Comment => Some("\x1b[90m"),
+                Token::Number if is_line_start && is_label(next) => {
+                    Some("\x1b[1;34m")
+                }
+                Token::Number if previous == Some(Token::Star) => {
+                    Some("\x1b[36m")
+                }
+                Token::Number if next == Some(Token::Slash) => Some("\x1b[36m"),
+                Token::Number if next == Some(Token::Minus) => Some("\x1b[33m"),
+                Token::Number => Some("\x1b[35m"),
+                Token::OpenExpr | Token::CloseExpr => Some("\x1b[1m"),
+                Token::Separator => Some("\x1b[2m"),
+                Token::Enter => None,
+                Token::Error if slice.trim().is_empty() => None,
+                Token::Error => Some("\x1b[4;31m"),
+                _ => Some("\x1b[31m"),
+            };
+            match colour {
+                Some(colour) => {
+                    output.push_str(colour);
+                    output.push_str(slice);
+                    output.push_str("\x1b[0m");
+                }
+                None => output.push_str(slice),
+            }
+            match token {
+                Token::Enter => is_line_start = true,
+                Token::Error if slice.trim().is_empty() => (),
+                _ => is_line_start = false,
+            }
+        }
+        Cow::Owned(output)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+/// Shows what is stored at an address right after typing `*` and a number.
+impl Hinter for ReplHelper {
+    type Hint = AddressHint;
+
+    fn hint(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> Option<AddressHint> {
+        if pos < line.len() || line.starts_with("---") {
+            return None;
+        }
+        let tokens: Vec<(Token, &str)> = lexer::tokens(line)
+            .map(|(token, _, slice)| (token, slice))
+            .collect();
+        let slice = match tokens.as_slice() {
+            [.., (Token::Number | Token::CloseExpr, _), (Token::Star, _), _] => {
+                return None
+            }
+            [.., (Token::Star, _), (Token::Number, slice)] => slice,
+            _ => return None,
+        };
+        let digits: String = slice.split_whitespace().collect();
+        let address: usize = digits.parse().ok()?;
+        let text = match self.tape.get(address) {
+            Some(expression) => deparse::deparse(expression),
+            None => "undefined".to_string(),
+        };
+        let text = if text.chars().count() > HINT_WIDTH {
+            let text: String = text.chars().take(HINT_WIDTH - 1).collect();
+            format!("{}…", text)
+        } else {
+            text
+        };
+        Some(AddressHint(format!("  ({} is {})", address, text)))
+    }
+}
+
+/// A hint that is only displayed, never inserted into the line.
+pub struct AddressHint(String);
+
+impl Hint for AddressHint {
+    fn display(&self) -> &str {
+        &self.0
+    }
+
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Refuses to submit while a `/.` is left unclosed, so that the input can
+/// be completed on the next line instead of failing to lex.
+impl Validator for ReplHelper {
+    fn validate(
+        &self,
+        ctx: &mut ValidationContext,
+    ) -> rustyline::Result<ValidationResult> {
+        if !ctx.input().trim().is_empty() {
+            return Ok(ValidationResult::Valid(None));
+        }
+        let depth =
+            lexer::tokens(&self.pending).fold(0, |depth, (token, _, _)| {
+                match token {
+                    Token::OpenExpr => depth + 1,
+                    Token::CloseExpr => depth - 1,
+                    _ => depth,
+                }
+            });
+        if depth > 0 {
+            let message = "  (unclosed /., continue on a line with ..)";
+            Ok(ValidationResult::Invalid(Some(message.to_string())))
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+/// A number at the start of a line is a label, unless the line is an
+/// expression to be evaluated at once.
+fn is_label(next: Option<Token>) -> bool {
+    matches!(next, None | Some(Token::Separator | Token::Enter))
+}
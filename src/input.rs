@@ -0,0 +1,93 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+
+/// Supplies the characters and numbers read by fetching from the input
+/// addresses. Returns None at the end of the input.
+pub trait InputSource {
+    fn read_char(&mut self) -> std::io::Result<Option<char>>;
+
+    /// Read a whitespace separated word.
+    fn read_word(&mut self) -> std::io::Result<Option<String>> {
+        let mut word = String::new();
+        while let Some(c) = self.read_char()? {
+            if !c.is_whitespace() {
+                word.push(c);
+            } else if !word.is_empty() {
+                break;
+            }
+        }
+        if word.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(word))
+        }
+    }
+}
+
+impl<R: BufRead> InputSource for R {
+    fn read_char(&mut self) -> std::io::Result<Option<char>> {
+        read_utf8_char(self)
+    }
+}
+
+/// Reads from stdin, locking it only for the duration of each read.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdinSource;
+
+impl InputSource for StdinSource {
+    fn read_char(&mut self) -> std::io::Result<Option<char>> {
+        read_utf8_char(&mut std::io::stdin().lock())
+    }
+}
+
+/// A scripted source that can be cloned and shared between threads, so
+/// that more input can be pushed after handing a clone to a machine.
+#[derive(Debug, Clone, Default)]
+pub struct InputBuffer {
+    chars: Arc<Mutex<VecDeque<char>>>,
+}
+
+impl InputBuffer {
+    pub fn new(text: &str) -> InputBuffer {
+        let buffer = InputBuffer::default();
+        buffer.push_str(text);
+        buffer
+    }
+
+    pub fn push_str(&self, text: &str) {
+        self.chars.lock().unwrap().extend(text.chars());
+    }
+}
+
+impl InputSource for InputBuffer {
+    fn read_char(&mut self) -> std::io::Result<Option<char>> {
+        Ok(self.chars.lock().unwrap().pop_front())
+    }
+}
+
+fn read_utf8_char(reader: &mut impl BufRead) -> std::io::Result<Option<char>> {
+    let mut bytes = [0; 4];
+    if reader.read(&mut bytes[0..1])? == 0 {
+        return Ok(None);
+    }
+    let len = match bytes[0] {
+        0x00..=0x7F => 1,
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        _ => 4,
+    };
+    reader.read_exact(&mut bytes[1..len])?;
+    match std::str::from_utf8(&bytes[0..len]) {
+        Ok(text) => Ok(text.chars().next()),
+        Err(error) => {
+            Err(std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+        }
+    }
+}
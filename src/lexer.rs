@@ -5,93 +5,171 @@
 //
 
 use crate::common::*;
+use crate::diagnostic::Diagnostic;
 
 use log::*;
 use logos::Logos;
 
-pub fn lex(source: &str) -> Result<Vec<LabelPass1>, anyhow::Error> {
-    let mut lex: logos::Lexer<Token> = Token::lexer(source);
+pub fn lex(source: &str) -> Result<Vec<LabelPass1>, Diagnostic> {
+    let (tree, diagnostics) = lex_all(source);
+    match diagnostics.into_iter().next() {
+        Some(diagnostic) => Err(diagnostic),
+        None => Ok(tree),
+    }
+}
+
+/// Lex the entire source, recovering from errors by skipping ahead to the
+/// next label at the start of a line. Labels that contain errors are left
+/// out of the result.
+pub fn lex_all(source: &str) -> (Vec<LabelPass1>, Vec<Diagnostic>) {
     let mut definition_end = false;
     let mut tree: Vec<LabelPass1> = vec![];
     let mut prev_num = false;
-    let mut current_token_tree: Vec<TokenTreePass1> = vec![];
-    let mut defer_nest: Vec<Vec<TokenTreePass1>> = vec![];
-    while let Some(token) = lex.next() {
+    let mut current_token_tree: Vec<Spanned<TokenTreePass1>> = vec![];
+    let mut defer_nest: Vec<(Vec<Spanned<TokenTreePass1>>, Span)> = vec![];
+    let mut diagnostics = vec![];
+    let mut is_skipping = false;
+    for (token, span, slice) in tokens(source) {
+        let is_on_new_line = definition_end;
         if token == Token::Error {
-            debug!("{:?}\t| {token:?} ", lex.slice())
+            debug!("{:?}\t| {token:?} ", slice)
         } else {
-            trace!("{:?}\t| {token:?} ", lex.slice().trim())
+            trace!("{:?}\t| {token:?} ", slice.trim())
         }
-        let mut operator = |x, y| -> Result<(), anyhow::Error> {
-            if definition_end {
-                Err(Error::ExpectedSeparator)?;
-            };
-            current_token_tree.push(if prev_num {
-                TokenTreePass1::Binary(x)
-            } else {
-                TokenTreePass1::Unary(y)
-            });
-            prev_num = false;
-            Ok(())
-        };
-
-        match token {
-            Token::Ceiling => operator(Binary::Abort, Unary::Ceiling)?,
-            Token::Floor => operator(Binary::Abort, Unary::Floor)?,
-            Token::Print => operator(Binary::Abort, Unary::Print)?,
-
-            Token::Star => operator(Binary::Mult, Unary::Fetch)?,
-            Token::Plus => operator(Binary::Plus, Unary::Signum)?,
-            Token::Minus => operator(Binary::Assign, Unary::Neg)?,
-            Token::Slash => operator(Binary::CallWith, Unary::Recip)?,
-
-            Token::OpenExpr => {
-                if prev_num || definition_end {
-                    Err(Error::ExpectedSeparator)?;
-                };
-                defer_nest.push(core::mem::take(&mut current_token_tree))
+        if is_skipping {
+            match token {
+                Token::Number if definition_end => is_skipping = false,
+                Token::Enter => (),
+                Token::Comment | Token::Error => continue,
+                _ => {
+                    // Keep track of nesting, so that a `/.` that is still
+                    // open at the next label can be reported.
+                    match token {
+                        Token::OpenExpr => defer_nest.push((vec![], span)),
+                        Token::CloseExpr => {
+                            defer_nest.pop();
+                        }
+                        _ => (),
+                    }
+                    definition_end = false;
+                    continue;
+                }
             }
-            Token::CloseExpr => {
-                if definition_end {
-                    Err(Error::ExpectedSeparator)?;
-                };
-                let last = defer_nest
-                    .last_mut()
-                    .ok_or_else(|| Error::UnbalancedDelimiter)?;
-                last.push(TokenTreePass1::NestExpr(core::mem::take(
-                    &mut current_token_tree,
-                )));
-                core::mem::swap(last, &mut current_token_tree);
-                defer_nest.pop().unwrap();
-            }
-            Token::Separator => {
+        }
+        // A label at the start of a line begins the next instruction, even if
+        // the previous one left a `/.` open.
+        if token == Token::Number && definition_end {
+            if let Some((_, span)) = defer_nest.pop() {
+                diagnostics.push(Error::UnclosedDelimiter { span }.into());
+                defer_nest.clear();
+                current_token_tree.clear();
                 definition_end = false;
-                prev_num = false;
-                current_token_tree.push(TokenTreePass1::Sep)
             }
-            Token::Number => {
+        }
+        let result = (|| -> Result<(), Diagnostic> {
+            let mut operator = |x, y| -> Result<(), Diagnostic> {
                 if definition_end {
-                    tree.push(LabelPass1(core::mem::take(
-                        &mut current_token_tree,
-                    )))
+                    Err(Error::ExpectedSeparator { span })?;
                 };
-                definition_end = false;
-                prev_num = true;
-                let src: String = lex.slice().split_whitespace().collect();
-                current_token_tree.push(if src.contains(".") {
-                    src.parse().map(TokenTreePass1::Float)?
+                let value = if prev_num {
+                    TokenTreePass1::Binary(x)
                 } else {
-                    src.parse().map(TokenTreePass1::Int)?
-                })
+                    TokenTreePass1::Unary(y)
+                };
+                current_token_tree.push(Spanned { value, span });
+                prev_num = false;
+                Ok(())
+            };
+
+            match token {
+                Token::Ceiling => operator(Binary::Abort, Unary::Ceiling)?,
+                Token::Floor => operator(Binary::Abort, Unary::Floor)?,
+                Token::Print => operator(Binary::Abort, Unary::Print)?,
+
+                Token::Star => operator(Binary::Mult, Unary::Fetch)?,
+                Token::Plus => operator(Binary::Plus, Unary::Signum)?,
+                Token::Minus => operator(Binary::Assign, Unary::Neg)?,
+                Token::Slash => operator(Binary::CallWith, Unary::Recip)?,
+
+                Token::OpenExpr => {
+                    if prev_num || definition_end {
+                        Err(Error::ExpectedSeparator { span })?;
+                    };
+                    let outer = core::mem::take(&mut current_token_tree);
+                    defer_nest.push((outer, span))
+                }
+                Token::CloseExpr => {
+                    if definition_end {
+                        Err(Error::ExpectedSeparator { span })?;
+                    };
+                    let (last, open_span) = defer_nest
+                        .last_mut()
+                        .ok_or(Error::UnbalancedDelimiter { span })?;
+                    last.push(Spanned {
+                        value: TokenTreePass1::NestExpr(core::mem::take(
+                            &mut current_token_tree,
+                        )),
+                        span: open_span.to(span),
+                    });
+                    core::mem::swap(last, &mut current_token_tree);
+                    defer_nest.pop().unwrap();
+                }
+                Token::Separator => {
+                    definition_end = false;
+                    prev_num = false;
+                    let value = TokenTreePass1::Sep;
+                    current_token_tree.push(Spanned { value, span })
+                }
+                Token::Number => {
+                    if definition_end {
+                        tree.push(LabelPass1(core::mem::take(
+                            &mut current_token_tree,
+                        )))
+                    };
+                    definition_end = false;
+                    prev_num = true;
+                    let src: String = slice.split_whitespace().collect();
+                    let value = if src.contains('.') {
+                        src.parse().map(TokenTreePass1::Float).ok()
+                    } else {
+                        src.parse().map(TokenTreePass1::Int).ok()
+                    };
+                    let value = value.ok_or(Error::InvalidNumber { span })?;
+                    current_token_tree.push(Spanned { value, span })
+                }
+                Token::Enter => {
+                    definition_end = true;
+                }
+                Token::Comment => {}
+                Token::Error if slice.trim() == "" => {}
+                Token::Error if slice.starts_with('(') => {}
+                Token::Error => Err(Error::Unstructured { span })?,
+            }
+            Ok(())
+        })();
+        if let Err(diagnostic) = result {
+            diagnostics.push(diagnostic);
+            // An error at the start of a line does not affect the instruction
+            // on the previous lines, which is complete.
+            if is_on_new_line
+                && defer_nest.is_empty()
+                && !current_token_tree.is_empty()
+            {
+                tree.push(LabelPass1(core::mem::take(&mut current_token_tree)))
             }
-            Token::Enter => {
-                definition_end = true;
+            current_token_tree.clear();
+            for (tokens, _) in defer_nest.iter_mut() {
+                tokens.clear();
             }
-            Token::Error if lex.slice().trim() == "" => {}
-            Token::Error if lex.slice().starts_with('(') => {}
-            Token::Error => Err(Error::Unstructured)?,
+            prev_num = false;
+            definition_end = false;
+            is_skipping = true;
         }
     }
+    if let Some((_, span)) = defer_nest.pop() {
+        diagnostics.push(Error::UnclosedDelimiter { span }.into());
+        current_token_tree.clear();
+    }
     tree.push(LabelPass1(core::mem::take(&mut current_token_tree)));
 
     trace!("");
@@ -99,15 +177,135 @@ pub fn lex(source: &str) -> Result<Vec<LabelPass1>, anyhow::Error> {
         {
             trace!("Label : ")
         };
-        for i in tokens.iter() {
-            trace!("\t{i:?}")
+        for Spanned { value, span } in tokens.iter() {
+            trace!("\t{span}\t{value:?}")
+        }
+    }
+    (tree, diagnostics)
+}
+
+/// The label of every instruction, along with its location from the label
+/// up to and including its last token. Instructions with errors are left out.
+pub fn instruction_spans(source: &str) -> Vec<(Integral, Span)> {
+    let (labels, _) = lex_all(source);
+    labels
+        .iter()
+        .filter_map(|LabelPass1(tokens)| {
+            let first = tokens.first()?;
+            let last = tokens.last()?;
+            match first.value {
+                TokenTreePass1::Int(label) => {
+                    Some((label, first.span.to(last.span)))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The locations of all comments in the source.
+pub fn comments(source: &str) -> Vec<Span> {
+    tokens(source)
+        .filter(|(token, _, _)| *token == Token::Comment)
+        .map(|(_, span, _)| span)
+        .collect()
+}
+
+/// How a literal number in the source refers to an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    /// The label of an instruction.
+    Label,
+    /// A fetch such as `*30`.
+    Fetch,
+    /// A call such as `40/...`.
+    Call,
+    /// An assignment such as `50-...`.
+    Assign,
+}
+
+/// A literal number in the source that refers to an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reference {
+    pub address: usize,
+    pub span: Span,
+    pub usage: Usage,
+}
+
+/// Every literal number that refers to an address, in order of appearance.
+/// Computed addresses such as in `*/.29+1./` are not included.
+pub fn references(source: &str) -> Vec<Reference> {
+    let tokens: Vec<(Token, Span, &str)> = tokens(source)
+        .filter(|(token, _, slice)| match token {
+            Token::Comment => false,
+            Token::Error => !slice.trim().is_empty(),
+            _ => true,
+        })
+        .collect();
+    let token_at = |i: Option<usize>| i.and_then(|i| tokens.get(i));
+    let mut references = Vec::new();
+    for (i, (token, span, slice)) in tokens.iter().enumerate() {
+        if *token != Token::Number {
+            continue;
         }
+        let digits: String = slice.split_whitespace().collect();
+        let address = match digits.parse::<usize>() {
+            Ok(address) => address,
+            Err(_) => continue,
+        };
+        let previous = token_at(i.checked_sub(1)).map(|(token, _, _)| *token);
+        let before = token_at(i.checked_sub(2)).map(|(token, _, _)| *token);
+        let next = token_at(Some(i + 1)).map(|(token, _, _)| *token);
+        // A star is only a fetch if there is no operand to its left.
+        let is_fetch = previous == Some(Token::Star)
+            && !matches!(before, Some(Token::Number | Token::CloseExpr));
+        let usage = if matches!(previous, None | Some(Token::Enter)) {
+            Usage::Label
+        } else if is_fetch {
+            Usage::Fetch
+        } else if next == Some(Token::Slash) {
+            Usage::Call
+        } else if next == Some(Token::Minus) {
+            Usage::Assign
+        } else {
+            continue;
+        };
+        references.push(Reference {
+            address,
+            span: *span,
+            usage,
+        });
     }
-    Ok(tree)
+    references
 }
 
-#[derive(Logos, Debug, PartialEq)]
-enum Token {
+/// Split the source into raw tokens, along with their location and text.
+/// Whitespace between tokens is part of the preceding token, or else an
+/// `Error` token of its own.
+pub fn tokens(source: &str) -> impl Iterator<Item = (Token, Span, &str)> {
+    let mut lex: logos::Lexer<Token> = Token::lexer(source);
+    let mut line = 1;
+    let mut line_start = 0;
+    std::iter::from_fn(move || {
+        let token = lex.next()?;
+        let slice = lex.slice();
+        let start = lex.span().start;
+        let span = Span {
+            start,
+            end: start + slice.trim_end().len(),
+            line,
+            column: source[line_start..start].chars().count() + 1,
+        };
+        if token == Token::Enter {
+            line += 1;
+            line_start = lex.span().end;
+        }
+        Some((token, span, slice))
+    })
+}
+
+#[derive(Logos, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
     // Operators
     #[regex(r"\*[ \t]*")]
     Star,
@@ -140,19 +338,31 @@ enum Token {
     // Display
     #[token("\n")]
     Enter,
+    #[token("(", comment)]
+    Comment,
     #[error]
-    #[regex(r"\(.*\)", logos::skip)]
     Error,
 }
 
-#[derive(Debug)]
-pub struct LabelPass1(pub Vec<TokenTreePass1>);
+/// A comment runs up to and including the last closing parenthesis on the
+/// line, so that it can contain parentheses of its own. Without one, it runs
+/// to the end of the line.
+fn comment(lex: &mut logos::Lexer<Token>) {
+    let line = lex.remainder().split('\n').next().unwrap_or_default();
+    match line.rfind(')') {
+        Some(end) => lex.bump(end + 1),
+        None => lex.bump(line.len()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelPass1(pub Vec<Spanned<TokenTreePass1>>);
 
 #[derive(Debug, Clone)]
 pub enum TokenTreePass1 {
     Int(Integral),
     Float(Float),
-    NestExpr(Vec<TokenTreePass1>),
+    NestExpr(Vec<Spanned<TokenTreePass1>>),
     Unary(Unary),
     Binary(Binary),
     Sep,
@@ -161,9 +371,45 @@ pub enum TokenTreePass1 {
 #[derive(Debug, thiserror::Error)]
 enum Error {
     #[error("Unbalanced delimiter")]
-    UnbalancedDelimiter,
+    UnbalancedDelimiter { span: Span },
+    #[error("Unclosed delimiter")]
+    UnclosedDelimiter { span: Span },
     #[error("Expected separator")]
-    ExpectedSeparator,
+    ExpectedSeparator { span: Span },
+    #[error("Invalid number")]
+    InvalidNumber { span: Span },
     #[error("Unstructured")]
-    Unstructured,
+    Unstructured { span: Span },
+}
+
+impl From<Error> for Diagnostic {
+    fn from(error: Error) -> Diagnostic {
+        let (code, span, hint) = match error {
+            Error::UnbalancedDelimiter { span } => (
+                "E0101",
+                span,
+                "remove this `./` or add a matching `/.` before it",
+            ),
+            Error::UnclosedDelimiter { span } => {
+                ("E0102", span, "add a matching `./` after this `/.`")
+            }
+            Error::ExpectedSeparator { span } => {
+                ("E0103", span, "did you mean `..` here?")
+            }
+            Error::InvalidNumber { span } => {
+                ("E0104", span, "integers must fit in 64 bits")
+            }
+            Error::Unstructured { span } => (
+                "E0105",
+                span,
+                "only numpad keys are allowed, comments go between `(` and `)`",
+            ),
+        };
+        Diagnostic {
+            code,
+            message: error.to_string(),
+            span,
+            hint: Some(hint),
+        }
+    }
 }
@@ -0,0 +1,223 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+//! A dynamically typed expression language that can be programmed using
+//! just one hand on the numpad.
+//!
+//! Source code is turned into instructions with [`lexer::lex`] followed by
+//! [`parser::parse`], which can then be loaded into a [`Machine`] and
+//! evaluated. For the common case of running an entire program from
+//! source, use [`run_source`].
+
+#![forbid(unsafe_code)]
+
+pub mod common;
+pub mod debugger;
+pub mod deparse;
+pub mod diagnostic;
+pub mod format;
+pub mod graph;
+pub mod input;
+pub mod lexer;
+pub mod lint;
+pub mod machine;
+pub mod minify;
+pub mod output;
+pub mod parser;
+pub mod tape;
+
+pub use crate::common::{
+    Binary, Expression, Float, Instruction, Integral, Span, Spanned, Unary,
+};
+pub use crate::diagnostic::Diagnostic;
+pub use crate::input::{InputBuffer, InputSource};
+pub use crate::machine::{
+    Event, Limits, Machine, RuntimeError, RuntimeErrorKind,
+};
+pub use crate::output::{OutputBuffer, OutputSink};
+pub use crate::tape::Tape;
+
+/// Lex and parse a program into instructions.
+pub fn compile(source: &str) -> Result<Vec<Instruction>, Diagnostic> {
+    let tokens = lexer::lex(source)?;
+    parser::parse(tokens)
+}
+
+/// Lex and parse a program, returning every instruction that could be
+/// parsed along with all errors found, in order of appearance.
+pub fn compile_all(source: &str) -> (Vec<Instruction>, Vec<Diagnostic>) {
+    let (tokens, mut diagnostics) = lexer::lex_all(source);
+    let (instructions, parse_diagnostics) = parser::parse_all(tokens);
+    diagnostics.extend(parse_diagnostics);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    (instructions, diagnostics)
+}
+
+/// Lex and parse a single expression that has no label, such as `98*/4`.
+/// Top-level separators make it a sequence, as in the body of an
+/// instruction.
+pub fn compile_expression(source: &str) -> Result<Expression, Vec<Diagnostic>> {
+    let (labels, mut diagnostics) = lexer::lex_all(source);
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    let mut tokens = vec![
+        Spanned {
+            value: lexer::TokenTreePass1::Int(0),
+            span: Span::default(),
+        },
+        Spanned {
+            value: lexer::TokenTreePass1::Sep,
+            span: Span::default(),
+        },
+    ];
+    for lexer::LabelPass1(label) in labels {
+        tokens.extend(label);
+    }
+    let label = lexer::LabelPass1(tokens);
+    let (mut instructions, parse_diagnostics) = parser::parse_all(vec![label]);
+    diagnostics.extend(parse_diagnostics);
+    match instructions.pop() {
+        Some(instruction) if diagnostics.is_empty() => {
+            Ok(instruction.expression)
+        }
+        _ => Err(diagnostics),
+    }
+}
+
+/// Lex, parse and evaluate a program, starting at address 1.
+/// Evaluation stops at the first runtime error.
+pub fn run_source(source: &str) -> Result<Expression, anyhow::Error> {
+    let instructions = compile(source)?;
+    let mut machine = Machine::create(instructions);
+    let output = machine.evaluate(1)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn examples() -> Vec<(String, String)> {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/examples");
+        let mut examples: Vec<(String, String)> = std::fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|x| x == "num"))
+            .map(|path| {
+                let source = std::fs::read_to_string(&path).unwrap();
+                (path.display().to_string(), source)
+            })
+            .collect();
+        examples.sort();
+        assert!(!examples.is_empty());
+        examples
+    }
+
+    /// The printed text and the deparsed result of evaluating address 1.
+    fn run(source: &str) -> (String, String) {
+        let mut machine = Machine::create(compile(source).unwrap());
+        let output = OutputBuffer::new();
+        machine.set_output(Box::new(output.clone()));
+        machine.set_input(Box::new(InputBuffer::new("")));
+        let result = match machine.evaluate(1) {
+            Ok(result) => deparse::deparse(&result),
+            Err(error) => error.to_string(),
+        };
+        (output.contents(), result)
+    }
+
+    #[test]
+    fn format_is_idempotent_on_examples() {
+        for (path, source) in examples() {
+            let formatted = format::format(&source).unwrap();
+            let again = format::format(&formatted).unwrap();
+            assert_eq!(again, formatted, "{}", path);
+        }
+    }
+
+    #[test]
+    fn format_keeps_the_behavior_of_examples() {
+        for (path, source) in examples() {
+            let formatted = format::format(&source).unwrap();
+            assert_eq!(run(&formatted), run(&source), "{}", path);
+        }
+    }
+
+    #[test]
+    fn minify_keeps_the_behavior_of_examples() {
+        for (path, source) in examples() {
+            let minified = minify::minify(&source).unwrap();
+            assert_eq!(run(&minified), run(&source), "{}", path);
+        }
+    }
+
+    #[test]
+    fn deparse_round_trips_examples() {
+        for (path, source) in examples() {
+            let instructions = compile(&source).unwrap();
+            let deparsed: Vec<String> = instructions
+                .iter()
+                .map(deparse::deparse_instruction)
+                .collect();
+            for text in &deparsed {
+                let reparsed = compile(text).unwrap();
+                let again = deparse::deparse_instruction(&reparsed[0]);
+                assert_eq!(&again, text, "{}", path);
+            }
+            assert_eq!(run(&deparsed.join("\n")), run(&source), "{}", path);
+        }
+    }
+
+    #[test]
+    fn error_on_new_line_keeps_previous_instruction() {
+        let (instructions, diagnostics) = compile_all("1..5\n*3\n2..7");
+        let labels: Vec<Integral> = instructions
+            .iter()
+            .map(|instruction| instruction.label)
+            .collect();
+        assert_eq!(labels, vec![1, 2]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.line, 2);
+    }
+
+    fn codes(diagnostics: &[Diagnostic]) -> Vec<(&'static str, usize)> {
+        diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.span.line))
+            .collect()
+    }
+
+    #[test]
+    fn error_after_unclosed_nest_reports_both() {
+        let (_, diagnostics) = compile_all("1 .. /.1..2\n2 .. x");
+        assert_eq!(codes(&diagnostics), [("E0102", 1), ("E0105", 2)]);
+        let (_, diagnostics) = compile_all("1 .. /.1 x 2./\n2 .. 3");
+        assert_eq!(codes(&diagnostics), [("E0105", 1)]);
+    }
+
+    #[test]
+    fn label_on_new_line_ends_unclosed_nest() {
+        let (instructions, diagnostics) =
+            compile_all("1 .. /.1..2\n2 .. 3\n3 .. 4 ./");
+        let labels: Vec<Integral> = instructions
+            .iter()
+            .map(|instruction| instruction.label)
+            .collect();
+        assert_eq!(labels, vec![2]);
+        assert_eq!(codes(&diagnostics), [("E0102", 1), ("E0101", 3)]);
+    }
+
+    #[test]
+    fn comments_can_contain_parentheses() {
+        for source in ["1..5 (a (b) c)", "1..5 (a) + (b)", "1..5 (a"] {
+            let output = run_source(source).unwrap();
+            assert!(
+                matches!(output, Expression::Number(number) if number == 5.0)
+            );
+        }
+    }
+}
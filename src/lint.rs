@@ -0,0 +1,304 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use crate::common::*;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{self, Reference, Token, Usage};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A mistake that can be spotted without running the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lint {
+    /// A literal fetch or call of an address that is never defined.
+    UndefinedAddress,
+    /// A literal assignment to address 0, which always fails at runtime.
+    WriteToZero,
+    /// An instruction with the same label as an earlier one, which it
+    /// silently replaces.
+    DuplicateLabel,
+    /// A fetch of an instruction that fetches its own address, which is
+    /// undefined behavior.
+    FetchedFunction,
+    /// A number with spaces inside it, which are ignored.
+    SpacedNumber,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 5] = [
+        Lint::UndefinedAddress,
+        Lint::WriteToZero,
+        Lint::DuplicateLabel,
+        Lint::FetchedFunction,
+        Lint::SpacedNumber,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UndefinedAddress => "undefined-address",
+            Lint::WriteToZero => "write-to-zero",
+            Lint::DuplicateLabel => "duplicate-label",
+            Lint::FetchedFunction => "fetched-function",
+            Lint::SpacedNumber => "spaced-number",
+        }
+    }
+
+    pub fn default_level(self) -> Level {
+        match self {
+            Lint::WriteToZero => Level::Deny,
+            _ => Level::Warn,
+        }
+    }
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for Lint {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Lint, String> {
+        Lint::ALL
+            .into_iter()
+            .find(|lint| lint.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> =
+                    Lint::ALL.iter().map(|lint| lint.name()).collect();
+                format!("expected one of {}", names.join(", "))
+            })
+    }
+}
+
+/// What to do when a lint is triggered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Ignore it.
+    Allow,
+    /// Report it as a warning.
+    Warn,
+    /// Report it as an error.
+    Deny,
+}
+
+/// The level of each lint, starting from their defaults.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    levels: BTreeMap<Lint, Level>,
+}
+
+impl Config {
+    pub fn set_level(&mut self, lint: Lint, level: Level) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        match self.levels.get(&lint) {
+            Some(level) => *level,
+            None => lint.default_level(),
+        }
+    }
+}
+
+/// A triggered lint, at a level other than [`Level::Allow`]. The lint name
+/// doubles as the diagnostic code.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub lint: Lint,
+    pub level: Level,
+    pub diagnostic: Diagnostic,
+}
+
+/// Look for likely mistakes in the source, ordered by location. The source
+/// is expected to compile; syntax errors are not reported here.
+pub fn check(source: &str, config: &Config) -> Vec<Finding> {
+    let references = lexer::references(source);
+    let mut findings = Vec::new();
+    let mut add = |lint: Lint, span: Span, message: String, hint| {
+        let level = config.level(lint);
+        if level == Level::Allow {
+            return;
+        }
+        let diagnostic = Diagnostic {
+            code: lint.name(),
+            message,
+            span,
+            hint: Some(hint),
+        };
+        findings.push(Finding {
+            lint,
+            level,
+            diagnostic,
+        });
+    };
+
+    let defined: BTreeSet<usize> = references
+        .iter()
+        .filter(|reference| {
+            matches!(reference.usage, Usage::Label | Usage::Assign)
+        })
+        .map(|reference| reference.address)
+        .collect();
+    let functions = functions(&references);
+    let mut labels: BTreeMap<usize, Span> = BTreeMap::new();
+    let mut label = None;
+    for reference in &references {
+        let Reference {
+            address,
+            span,
+            usage,
+        } = *reference;
+        match usage {
+            Usage::Label => {
+                if let Some(earlier) = labels.insert(address, span) {
+                    add(
+                        Lint::DuplicateLabel,
+                        span,
+                        format!(
+                            "Instruction {} is already defined on line {}",
+                            address, earlier.line
+                        ),
+                        "only the last instruction with a label is kept, \
+                         so the earlier one is ignored",
+                    );
+                }
+                label = Some(address);
+            }
+            Usage::Assign if address == 0 => add(
+                Lint::WriteToZero,
+                span,
+                "Assignment to address 0".to_string(),
+                "address 0 holds the argument of a call and cannot be \
+                 written to",
+            ),
+            Usage::Fetch | Usage::Call
+                if address != 0 && !defined.contains(&address) =>
+            {
+                add(
+                    Lint::UndefinedAddress,
+                    span,
+                    format!("Address {} is never defined", address),
+                    "add an instruction with this label, or assign to it \
+                     before using it",
+                )
+            }
+            Usage::Fetch
+                if functions.contains(&address) && label != Some(address) =>
+            {
+                add(
+                    Lint::FetchedFunction,
+                    span,
+                    format!("Fetching function {}", address),
+                    "fetching an instruction that fetches its own address \
+                     is undefined behavior, call it with `/` instead",
+                )
+            }
+            _ => (),
+        }
+    }
+
+    for (token, span, slice) in lexer::tokens(source) {
+        if token == Token::Number && slice.trim().contains([' ', '\t']) {
+            let digits: String = slice.split_whitespace().collect();
+            add(
+                Lint::SpacedNumber,
+                span,
+                format!("`{}` is read as the number {}", slice.trim(), digits),
+                "spaces inside a number are ignored, use `..` or an \
+                 operator to separate numbers",
+            );
+        }
+    }
+
+    findings.sort_by_key(|finding| finding.diagnostic.span.start);
+    findings
+}
+
+/// The labels of instructions that contain a literal fetch of their own
+/// address, such as `9000 .. 50 + *9000`.
+fn functions(references: &[Reference]) -> BTreeSet<usize> {
+    let mut functions = BTreeSet::new();
+    let mut label = None;
+    for reference in references {
+        match reference.usage {
+            Usage::Label => label = Some(reference.address),
+            Usage::Fetch if label == Some(reference.address) => {
+                functions.insert(reference.address);
+            }
+            _ => (),
+        }
+    }
+    functions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lints(source: &str) -> Vec<(Lint, usize)> {
+        check(source, &Config::default())
+            .into_iter()
+            .map(|finding| (finding.lint, finding.diagnostic.span.line))
+            .collect()
+    }
+
+    #[test]
+    fn undefined_address() {
+        assert_eq!(lints("1 .. *30"), [(Lint::UndefinedAddress, 1)]);
+        assert_eq!(lints("1 .. 40/5"), [(Lint::UndefinedAddress, 1)]);
+        // A star after an operand is a multiplication, not a fetch.
+        assert_eq!(lints("1 .. 3 *30"), []);
+        // Assigned addresses count as defined, and so does address 0.
+        assert_eq!(lints("1 .. 50 - 1 .. *50 .. *0"), []);
+        assert_eq!(lints("1 .. 2/3\n2 .. *0"), []);
+    }
+
+    #[test]
+    fn write_to_zero() {
+        let findings = check("1 .. 0 - 5", &Config::default());
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].lint, Lint::WriteToZero);
+        assert_eq!(findings[0].level, Level::Deny);
+        assert_eq!(findings[0].diagnostic.code, "write-to-zero");
+    }
+
+    #[test]
+    fn duplicate_label() {
+        assert_eq!(lints("1 .. 5\n1 .. 6"), [(Lint::DuplicateLabel, 2)]);
+    }
+
+    #[test]
+    fn fetched_function() {
+        let function = "9000 .. 50 + *9000";
+        assert_eq!(
+            lints(&format!("1 .. *9000\n{}", function)),
+            [(Lint::FetchedFunction, 1)]
+        );
+        // Calling it is fine, and so is the fetch of its own argument.
+        assert_eq!(lints(&format!("1 .. 9000/4\n{}", function)), []);
+    }
+
+    #[test]
+    fn spaced_number() {
+        assert_eq!(lints("1 .. 1 2"), [(Lint::SpacedNumber, 1)]);
+        assert_eq!(lints("1 .. 12"), []);
+    }
+
+    #[test]
+    fn levels_can_be_overridden() {
+        let mut config = Config::default();
+        config.set_level(Lint::WriteToZero, Level::Allow);
+        config.set_level(Lint::UndefinedAddress, Level::Deny);
+        let findings = check("1 .. 0 - 5 .. *30", &config);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].lint, Lint::UndefinedAddress);
+        assert_eq!(findings[0].level, Level::Deny);
+        assert_eq!("spaced-number".parse(), Ok(Lint::SpacedNumber));
+        assert!("unknown".parse::<Lint>().is_err());
+    }
+}
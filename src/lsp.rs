@@ -0,0 +1,284 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use crate::protocol;
+use numpad::common::*;
+use numpad::deparse;
+use numpad::lexer::{self, references, Usage};
+use numpad::lint::{self, Level};
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const FULL_SYNC: u64 = 1;
+const ERROR_SEVERITY: u64 = 1;
+const WARNING_SEVERITY: u64 = 2;
+const FUNCTION_SYMBOL: u64 = 12;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Speak the Language Server Protocol over stdin and stdout until the client
+/// sends the exit notification.
+pub fn serve() -> anyhow::Result<()> {
+    let mut stdin = std::io::stdin().lock();
+    let mut documents: HashMap<String, String> = HashMap::new();
+    while let Some(message) = protocol::read_message(&mut stdin)? {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let source = documents.get(uri).map(String::as_str).unwrap_or_default();
+        let position = &params["position"];
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": FULL_SYNC,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "referencesProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "numpad" },
+            }),
+            "shutdown" => Value::Null,
+            "exit" => break,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str();
+                let text = text.unwrap_or_default().to_string();
+                publish_diagnostics(uri, &text)?;
+                documents.insert(uri.to_string(), text);
+                continue;
+            }
+            "textDocument/didChange" => {
+                // With full synchronization, the last change is the whole
+                // document.
+                let changes = params["contentChanges"].as_array();
+                let text = changes
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                let text = text.unwrap_or_default().to_string();
+                publish_diagnostics(uri, &text)?;
+                documents.insert(uri.to_string(), text);
+                continue;
+            }
+            "textDocument/didClose" => {
+                documents.remove(uri);
+                let params = json!({ "uri": uri, "diagnostics": [] });
+                notify("textDocument/publishDiagnostics", params)?;
+                continue;
+            }
+            "textDocument/definition" => {
+                let locations: Vec<Value> = address_at(source, position)
+                    .map(|address| {
+                        definitions(source, address)
+                            .map(|span| location(uri, source, span))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                json!(locations)
+            }
+            "textDocument/hover" => match address_at(source, position) {
+                Some(address) => {
+                    let text = describe(source, address);
+                    json!({
+                        "contents": {
+                            "kind": "markdown",
+                            "value": format!("```numpad\n{}\n```", text),
+                        },
+                    })
+                }
+                None => Value::Null,
+            },
+            "textDocument/references" => {
+                let context = &params["context"];
+                let is_declaration_included =
+                    context["includeDeclaration"].as_bool().unwrap_or(true);
+                let locations: Vec<Value> = match address_at(source, position) {
+                    Some(address) => references(source)
+                        .into_iter()
+                        .filter(|reference| reference.address == address)
+                        .filter(|reference| {
+                            is_declaration_included
+                                || reference.usage != Usage::Label
+                        })
+                        .map(|reference| location(uri, source, reference.span))
+                        .collect(),
+                    None => Vec::new(),
+                };
+                json!(locations)
+            }
+            "textDocument/documentSymbol" => {
+                json!(document_symbols(source))
+            }
+            _ => {
+                if !message["id"].is_null() {
+                    protocol::write_message(&json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "error": {
+                            "code": METHOD_NOT_FOUND,
+                            "message": format!("Unsupported method {}", method),
+                        },
+                    }))?;
+                }
+                continue;
+            }
+        };
+        protocol::write_message(&json!({
+            "jsonrpc": "2.0",
+            "id": message["id"],
+            "result": result,
+        }))?;
+    }
+    Ok(())
+}
+
+fn notify(method: &str, params: Value) -> anyhow::Result<()> {
+    protocol::write_message(&json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+    }))
+}
+
+fn publish_diagnostics(uri: &str, source: &str) -> anyhow::Result<()> {
+    let (_, mut diagnostics) = numpad::compile_all(source);
+    let mut severities = vec![ERROR_SEVERITY; diagnostics.len()];
+    // Lints are only meaningful for source code that compiles.
+    if diagnostics.is_empty() {
+        for finding in lint::check(source, &lint::Config::default()) {
+            severities.push(match finding.level {
+                Level::Deny => ERROR_SEVERITY,
+                _ => WARNING_SEVERITY,
+            });
+            diagnostics.push(finding.diagnostic);
+        }
+    }
+    let diagnostics: Vec<Value> = diagnostics
+        .iter()
+        .zip(severities)
+        .map(|(diagnostic, severity)| {
+            let message = match diagnostic.hint {
+                Some(hint) => format!("{}\nhint: {}", diagnostic.message, hint),
+                None => diagnostic.message.clone(),
+            };
+            json!({
+                "range": range(source, diagnostic.span),
+                "severity": severity,
+                "code": diagnostic.code,
+                "source": "numpad",
+                "message": message,
+            })
+        })
+        .collect();
+    let params = json!({ "uri": uri, "diagnostics": diagnostics });
+    notify("textDocument/publishDiagnostics", params)
+}
+
+fn definitions(
+    source: &str,
+    address: usize,
+) -> impl Iterator<Item = Span> + '_ {
+    references(source)
+        .into_iter()
+        .filter(move |reference| reference.usage == Usage::Label)
+        .filter(move |reference| reference.address == address)
+        .map(|reference| reference.span)
+}
+
+/// The address that the number at the given position refers to.
+fn address_at(source: &str, position: &Value) -> Option<usize> {
+    let offset = offset(source, position)?;
+    references(source)
+        .into_iter()
+        .find(|reference| {
+            reference.span.start <= offset && offset <= reference.span.end
+        })
+        .map(|reference| reference.address)
+}
+
+fn describe(source: &str, address: usize) -> String {
+    let (instructions, _) = numpad::compile_all(source);
+    // Later instructions overwrite earlier ones with the same label.
+    let instruction = instructions
+        .into_iter()
+        .rev()
+        .find(|instruction| instruction.label == address);
+    match instruction {
+        Some(instruction) => deparse::deparse_instruction(&instruction),
+        None => format!("{} is not defined in this file", address),
+    }
+}
+
+/// Every instruction, named after its label. Comments on the same line as
+/// the label are shown as details.
+fn document_symbols(source: &str) -> Vec<Value> {
+    let comments = lexer::comments(source);
+    lexer::instruction_spans(source)
+        .into_iter()
+        .map(|(label, span)| {
+            let detail = comments
+                .iter()
+                .find(|comment| comment.line == span.line)
+                .map(|comment| &source[comment.start..comment.end]);
+            let label_end = span.start
+                + source[span.start..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(span.end - span.start);
+            let label_span = Span {
+                end: label_end,
+                ..span
+            };
+            let mut symbol = json!({
+                "name": label.to_string(),
+                "kind": FUNCTION_SYMBOL,
+                "range": range(source, span),
+                "selectionRange": range(source, label_span),
+            });
+            if let Some(detail) = detail {
+                symbol["detail"] = json!(detail);
+            }
+            symbol
+        })
+        .collect()
+}
+
+fn location(uri: &str, source: &str, span: Span) -> Value {
+    json!({ "uri": uri, "range": range(source, span) })
+}
+
+fn range(source: &str, span: Span) -> Value {
+    json!({
+        "start": position(source, span.start),
+        "end": position(source, span.end),
+    })
+}
+
+/// Positions count lines from zero and characters in UTF-16 code units.
+fn position(source: &str, offset: usize) -> Value {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count();
+    let character = before[line_start..].encode_utf16().count();
+    json!({ "line": line, "character": character })
+}
+
+fn offset(source: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let line_start = if line == 0 {
+        0
+    } else {
+        source.match_indices('\n').nth(line - 1)?.0 + 1
+    };
+    let mut units = 0;
+    for (i, c) in source[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(source.len())
+}
@@ -5,63 +5,406 @@
 //
 
 use crate::common::*;
+use crate::deparse::{deparse, deparse_instruction};
+use crate::input::{InputSource, StdinSource};
+use crate::output::OutputSink;
+use crate::tape::Tape;
 
 use log::*;
-use std::process::abort;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 pub struct Machine {
-    tape: Vec<Expression>,
+    tape: Tape,
     call_stack: Vec<EvaluationInProgress>,
     fetched: Expression,
     instruction_address: usize,
+    is_lenient: bool,
+    limits: Limits,
+    error: Option<RuntimeError>,
+    output: Box<dyn OutputSink + Send>,
+    input: Box<dyn InputSource + Send>,
+    span: Option<Span>,
+    interrupt: Option<Arc<AtomicBool>>,
+    event: Option<Event>,
 }
 
+/// Fetching from this address reads the codepoint of the next character.
+pub const INPUT_CHAR_ADDRESS: Float = -1.0;
+
+/// Fetching from this address reads the next whitespace separated number.
+pub const INPUT_NUMBER_ADDRESS: Float = -2.0;
+
+/// Whether fetching from this number reads input. Other negative numbers
+/// refer to address 0, like any number below 0.5.
+pub fn is_input_address(number: Float) -> bool {
+    let address = number.round();
+    address == INPUT_CHAR_ADDRESS || address == INPUT_NUMBER_ADDRESS
+}
+
+/// Resource limits that stop evaluation when exceeded, regardless of whether
+/// the machine is lenient. All limits are disabled by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// The maximum number of ticks per evaluation.
+    pub max_steps: Option<u64>,
+    /// The maximum number of defined addresses on the tape while evaluating.
+    pub max_tape_size: Option<usize>,
+    /// The maximum number of nested evaluations.
+    pub max_call_depth: Option<usize>,
+    /// The maximum number of elements in a list on the tape.
+    pub max_list_len: Option<usize>,
+}
+
+/// Something that happened during a step, of interest to a debugger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The instruction at this address was called.
+    Call { address: usize },
+    /// This address was written to, either entirely or one of its elements.
+    Write { address: usize },
+}
+
+/// A runtime error, along with the instruction that was being evaluated and
+/// the location of the operator that was performed most recently.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub address: usize,
+    pub span: Option<Span>,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} while evaluating {}", self.kind, self.address)?;
+        match self.span {
+            Some(span) => write!(f, " at {}", span),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum RuntimeErrorKind {
+    #[error("Illegal write to address 0")]
+    IllegalWriteToAddressZero,
+    #[error("Value is abnormal: {value}")]
+    AbnormalValue { value: Float },
+    #[error("Value is too high: {value}")]
+    ValueTooHigh { value: Float },
+    #[error("Codepoint is invalid: {value}")]
+    InvalidCodepoint { value: Float },
+    #[error("Cannot fetch from empty list")]
+    EmptyList,
+    #[error("Expected a list at {address}, got {got}")]
+    NotAList { address: usize, got: Expression },
+    #[error("Index out of bounds: {offset} in list at {address}")]
+    ElementOutOfBounds { address: usize, offset: usize },
+    #[error("{operator:?} is unimplemented for {operand}")]
+    UnsupportedUnary {
+        operator: Unary,
+        operand: Expression,
+    },
+    #[error("{operator:?} is unimplemented for {operand}")]
+    UnsupportedBinary {
+        operator: Binary,
+        operand: Expression,
+    },
+    #[error("Step limit of {limit} exceeded")]
+    StepLimitExceeded { limit: u64 },
+    #[error("Tape size limit of {limit} exceeded by write to {address}")]
+    TapeLimitExceeded { address: usize, limit: usize },
+    #[error("Call depth limit of {limit} exceeded")]
+    CallDepthExceeded { limit: usize },
+    #[error("List length limit of {limit} exceeded by write to {address}")]
+    ListLimitExceeded { address: usize, limit: usize },
+    #[error("Aborting program")]
+    Aborted,
+    #[error("Interrupted at call depth {depth}")]
+    Interrupted { depth: usize },
+    #[error("Failed to print: {reason}")]
+    OutputFailed { reason: String },
+    #[error("Failed to read input: {reason}")]
+    InputFailed { reason: String },
+    #[error("Expected a number as input, got {word:?}")]
+    InvalidNumberInput { word: String },
+}
+
+/// An expression on the call stack, which is partially evaluated.
 #[derive(Debug)]
-struct EvaluationInProgress {
-    expression: Expression,
+pub struct EvaluationInProgress {
+    pub expression: Expression,
+    /// The address of the instruction that the expression is part of.
+    pub address: usize,
 }
 
 impl Machine {
     pub fn create(program: Vec<Instruction>) -> Machine {
-        let mut tape = Vec::new();
-        let min_tape_size: usize = program
-            .iter()
-            .map(|instruction| instruction.label)
-            .max()
-            .unwrap_or_default();
-        tape.resize(min_tape_size + 1, Default::default());
+        let mut tape = Tape::new();
         for instruction in program {
-            tape[instruction.label] = instruction.expression;
+            tape.set(instruction.label, instruction.expression);
         }
         Machine {
             tape,
             call_stack: Vec::new(),
             fetched: Expression::default(),
             instruction_address: 0,
+            is_lenient: false,
+            limits: Limits::default(),
+            error: None,
+            output: Box::new(std::io::stdout()),
+            input: Box::new(StdinSource),
+            span: None,
+            interrupt: None,
+            event: None,
         }
     }
 
     pub fn update(&mut self, program_update: Vec<Instruction>) {
-        let Machine { tape, .. }: &mut Machine = self;
-        let min_tape_size: usize = program_update
-            .iter()
-            .map(|instruction| instruction.label)
-            .max()
-            .unwrap_or_default()
-            .max(tape.len());
-        tape.resize(min_tape_size + 1, Default::default());
         for instruction in program_update {
-            tape[instruction.label] = instruction.expression;
+            self.tape.set(instruction.label, instruction.expression);
+        }
+    }
+
+    /// Load instructions written by `dump` onto the tape. The setup
+    /// instruction at address 0, if any, is evaluated once to assign the
+    /// values that have no literal and is then cleared.
+    pub fn load_dump(
+        &mut self,
+        dump: Vec<Instruction>,
+    ) -> Result<(), RuntimeError> {
+        let has_setup = dump.iter().any(|instruction| instruction.label == 0);
+        self.update(dump);
+        if !has_setup {
+            return Ok(());
         }
+        let result = self.evaluate(0);
+        self.tape.set(0, Expression::Undefined);
+        result.map(|_| ())
+    }
+
+    /// When lenient, runtime errors are logged and the failing operation
+    /// yields undefined instead of stopping evaluation.
+    pub fn set_lenient(&mut self, is_lenient: bool) {
+        self.is_lenient = is_lenient;
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Replace the sink that receives printed characters. By default,
+    /// characters are printed to stdout. The sink is flushed at the end of
+    /// each evaluation.
+    pub fn set_output(&mut self, output: Box<dyn OutputSink + Send>) {
+        self.output = output;
+    }
+
+    /// Replace the source that is read from when fetching from the input
+    /// addresses. By default, input is read from stdin.
+    pub fn set_input(&mut self, input: Box<dyn InputSource + Send>) {
+        self.input = input;
+    }
+
+    /// Stop evaluation as soon as the flag is set, for example by a Ctrl-C
+    /// handler. The flag is cleared at the start of each evaluation.
+    pub fn set_interrupt(&mut self, interrupt: Arc<AtomicBool>) {
+        self.interrupt = Some(interrupt);
+    }
+
+    /// Evaluate the expression at the given address, stopping at the first
+    /// runtime error unless the machine is lenient.
+    pub fn evaluate(
+        &mut self,
+        address: usize,
+    ) -> Result<Expression, RuntimeError> {
+        self.start(address);
+        self.run_to_end();
+        self.finish()
+    }
+
+    /// Evaluate an expression that is not stored on the tape, as if it were
+    /// called without arguments. It can read from the tape, but any changes
+    /// it makes to the tape are undone afterwards.
+    pub fn evaluate_expression(
+        &mut self,
+        expression: Expression,
+    ) -> Result<Expression, RuntimeError> {
+        let tape = self.tape.clone();
+        self.reset();
+        self.push(expression, 0);
+        self.set_called_with(Expression::Undefined);
+        self.run_to_end();
+        let output = self.finish();
+        self.tape = tape;
+        output
     }
 
+    /// Evaluate the expression at the given address leniently. If a limit
+    /// is exceeded, the result is undefined.
     pub fn evaluate_until_finished(&mut self, address: usize) -> Expression {
+        let is_lenient = std::mem::replace(&mut self.is_lenient, true);
+        let output = self.evaluate(address).unwrap_or_else(|error| {
+            error!("{}", error);
+            Expression::Undefined
+        });
+        self.is_lenient = is_lenient;
+        output
+    }
+
+    pub fn tape(&self) -> &Tape {
+        &self.tape
+    }
+
+    /// Replace the whole tape, for example with a clone of an earlier one.
+    pub fn restore(&mut self, tape: Tape) {
+        self.tape = tape;
+    }
+
+    /// Write every defined address as source code, such that loading it
+    /// with `load_dump` restores the tape. The call argument at address 0 is
+    /// left out. Values without a literal of their own, such as negative
+    /// numbers, would be evaluated as instructions when fetched, so they are
+    /// assigned by a setup instruction at address 0 instead. Undefined has
+    /// no source that cannot be shadowed by a later write, so addresses that
+    /// hold an undefined element are left out, with a comment.
+    pub fn dump(&self) -> String {
+        let mut output = String::new();
+        let mut setup = Vec::new();
+        for (address, expression) in self.tape.iter() {
+            if address == 0 {
+                continue;
+            }
+            if contains_undefined(expression) {
+                warn!("Leaving {} out of the dump", address);
+                output.push_str(&format!(
+                    "({} is left out, because it holds undefined)\n",
+                    address
+                ));
+            } else if has_no_literal(expression) {
+                setup.push(format!("{} - {}", address, deparse(expression)));
+            } else {
+                let instruction = Instruction {
+                    label: address,
+                    expression: expression.clone(),
+                    span: Span::default(),
+                };
+                output.push_str(&deparse_instruction(&instruction));
+                output.push('\n');
+            }
+        }
+        if !setup.is_empty() {
+            output.push('0');
+            for statement in setup {
+                output.push_str("\n.. ");
+                output.push_str(&statement);
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    /// The location of the operator that was performed most recently.
+    pub fn current_span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// Begin evaluating the expression at the given address, without
+    /// performing any steps yet.
+    pub fn start(&mut self, address: usize) {
+        self.reset();
+        self.fetch(address);
+    }
+
+    /// Perform a single step of the evaluation that was started, reporting
+    /// whether it called an instruction or wrote to the tape.
+    pub fn step(&mut self) -> Option<Event> {
+        self.event = None;
+        if !self.is_finished() {
+            self.tick();
+            self.flush_output();
+        }
+        self.event.take()
+    }
+
+    /// End the evaluation and take its result. An evaluation that has not
+    /// finished yet is abandoned.
+    pub fn finish(&mut self) -> Result<Expression, RuntimeError> {
+        self.flush_output();
+        let output = self.take_output();
+        self.call_stack.clear();
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(output),
+        }
+    }
+
+    /// Whether the evaluation has ended, either with a result or an error.
+    pub fn is_finished(&self) -> bool {
+        self.call_stack.is_empty() || self.error.is_some()
+    }
+
+    /// The expressions that are being evaluated, innermost last.
+    pub fn call_stack(&self) -> &[EvaluationInProgress] {
+        &self.call_stack
+    }
+
+    /// The value that was produced most recently.
+    pub fn fetched(&self) -> &Expression {
+        &self.fetched
+    }
+
+    /// The address of the instruction that was called most recently, whose
+    /// call argument is stored at address 0.
+    pub fn instruction_address(&self) -> usize {
+        self.instruction_address
+    }
+
+    fn reset(&mut self) {
         trace!("");
+        self.error = None;
+        self.span = None;
         self.instruction_address = 0;
-        self.fetch(address);
+        self.event = None;
+        if let Some(interrupt) = &self.interrupt {
+            interrupt.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn run_to_end(&mut self) {
+        let mut steps: u64 = 0;
         while !self.is_finished() {
+            if let Some(interrupt) = &self.interrupt {
+                if interrupt.swap(false, Ordering::SeqCst) {
+                    // The address is that of the innermost evaluation.
+                    let error = RuntimeErrorKind::Interrupted {
+                        depth: self.call_stack.len(),
+                    };
+                    self.halt(error);
+                    break;
+                }
+            }
+            if let Some(limit) = self.limits.max_steps {
+                if steps >= limit {
+                    self.halt(RuntimeErrorKind::StepLimitExceeded { limit });
+                    break;
+                }
+            }
+            steps += 1;
             self.tick();
         }
+    }
+
+    fn flush_output(&mut self) {
+        if let Err(error) = self.output.flush() {
+            let reason = error.to_string();
+            self.fail(RuntimeErrorKind::OutputFailed { reason });
+        }
+    }
+
+    fn take_output(&mut self) -> Expression {
         let expression = std::mem::take(&mut self.fetched);
         match expression {
             Expression::Undefined => {
@@ -71,7 +414,13 @@ impl Machine {
             Expression::Number(_) => expression,
             Expression::List(_) => expression,
             Expression::PointerIntoList { address, offset } => {
-                self.copy_list(address, offset)
+                match self.copy_list(address, offset) {
+                    Ok(list) => list,
+                    Err(error) => {
+                        self.fail(error);
+                        Expression::Undefined
+                    }
+                }
             }
             Expression::Sequence(_) => unreachable!(),
             Expression::Unary { .. } => unreachable!(),
@@ -80,22 +429,76 @@ impl Machine {
         }
     }
 
-    fn is_finished(&self) -> bool {
-        self.call_stack.is_empty()
+    fn fail(&mut self, kind: RuntimeErrorKind) {
+        let error = self.error_at_current_address(kind);
+        error!("{}", error);
+        if !self.is_lenient && self.error.is_none() {
+            self.error = Some(error);
+        }
+        self.fetched = Expression::Undefined;
     }
 
-    fn fetch(&mut self, address: usize) {
-        let expression = self.tape.get(address).cloned().unwrap_or_else(|| {
-            error!("Index out of bounds: {}", address);
-            Expression::Undefined
+    fn halt(&mut self, kind: RuntimeErrorKind) {
+        if self.error.is_none() {
+            self.error = Some(self.error_at_current_address(kind));
+        }
+        self.fetched = Expression::Undefined;
+    }
+
+    fn error_at_current_address(&self, kind: RuntimeErrorKind) -> RuntimeError {
+        RuntimeError {
+            kind,
+            address: self.current_address(),
+            span: self.span,
+        }
+    }
+
+    /// The address of the instruction that is being evaluated.
+    fn current_address(&self) -> usize {
+        match self.call_stack.last() {
+            Some(evaluation) => evaluation.address,
+            None => self.instruction_address,
+        }
+    }
+
+    fn push(&mut self, expression: Expression, address: usize) {
+        if let Some(limit) = self.limits.max_call_depth {
+            if self.call_stack.len() >= limit {
+                return self
+                    .halt(RuntimeErrorKind::CallDepthExceeded { limit });
+            }
+        }
+        self.call_stack.push(EvaluationInProgress {
+            expression,
+            address,
         });
+    }
+
+    fn unsupported_unary(&mut self, operator: Unary, operand: Expression) {
+        self.fail(RuntimeErrorKind::UnsupportedUnary { operator, operand });
+    }
+
+    fn unsupported_binary(&mut self, operator: Binary, operand: Expression) {
+        self.fail(RuntimeErrorKind::UnsupportedBinary { operator, operand });
+    }
+
+    fn fetch(&mut self, address: usize) {
+        if address > 0 && address == self.instruction_address {
+            trace!("Accessing call argument for {}", address);
+            return self.fetch(0);
+        }
+        let expression = self.tape.get(address).cloned().unwrap_or_default();
         match expression {
-            _ if address > 0 && address == self.instruction_address => {
-                trace!("Accessing call argument for {}", address);
-                self.fetch(0)
-            }
             Expression::Undefined => {
-                warn!("Access undefined register {}", address);
+                match self.span {
+                    Some(span) => {
+                        warn!(
+                            "Access undefined register {} at {}",
+                            address, span
+                        )
+                    }
+                    None => warn!("Access undefined register {}", address),
+                }
                 self.fetched = Expression::Undefined;
             }
             Expression::Number(_) => {
@@ -115,55 +518,52 @@ impl Machine {
             | Expression::Unary { .. }
             | Expression::Binary { .. } => {
                 trace!("Evaluating {}: {}", address, expression);
-                self.call_stack.push(EvaluationInProgress { expression });
+                self.instruction_address = address;
+                self.push(expression, address);
                 self.fetched = Expression::Undefined;
                 self.set_called_with(Expression::Undefined);
-                self.instruction_address = address;
+                self.event = Some(Event::Call { address });
             }
             Expression::Stub => unreachable!(),
         }
     }
 
     fn set_called_with(&mut self, expression: Expression) {
-        if let Some(stored) = self.tape.get_mut(0) {
-            match &expression {
-                Expression::Undefined => trace!("Clearing call arguments"),
-                _ => trace!("Setting call argument to {}", expression),
-            }
-            *stored = expression;
+        match &expression {
+            Expression::Undefined => trace!("Clearing call arguments"),
+            _ => trace!("Setting call argument to {}", expression),
         }
+        self.tape.set(0, expression);
     }
 
     fn store(&mut self, address: usize, expression: Expression) {
-        match self.tape.get_mut(address) {
-            _ if address == 0 => {
-                // Writing to address 0 is disallowed, because it is used
-                // internally to store the call arguments.
-                error!("Illegal write to address 0");
-                self.fetched = Expression::Undefined;
-            }
-            Some(stored) => {
-                trace!("Writing to {}: {}", address, expression);
-                *stored = expression;
-                self.fetched = Expression::Undefined;
-            }
-            None => {
-                let size = address + 1;
-                info!("Extending tape to size {size}");
-                self.tape.resize_with(size, || Expression::default());
-                self.tape[address] = expression;
-                self.fetched = Expression::Undefined;
+        if address == 0 {
+            // Writing to address 0 is disallowed, because it is used
+            // internally to store the call arguments.
+            return self.fail(RuntimeErrorKind::IllegalWriteToAddressZero);
+        }
+        if !self.tape.is_defined(address) {
+            if let Some(limit) = self.limits.max_tape_size {
+                if self.tape.len() >= limit {
+                    let error =
+                        RuntimeErrorKind::TapeLimitExceeded { address, limit };
+                    return self.halt(error);
+                }
             }
         }
+        trace!("Writing to {}: {}", address, expression);
+        self.tape.set(address, expression);
+        self.fetched = Expression::Undefined;
+        self.event = Some(Event::Write { address });
     }
 
     fn tick(&mut self) {
         trace!("");
-        for EvaluationInProgress { expression } in self.call_stack.iter() {
+        for EvaluationInProgress { expression, .. } in self.call_stack.iter() {
             trace!("Eval :: {}", expression);
         }
-        match self.call_stack.last_mut() {
-            Some(evaluation) => match &mut evaluation.expression {
+        if let Some(evaluation) = self.call_stack.last_mut() {
+            match &mut evaluation.expression {
                 Expression::Undefined
                 | Expression::Number(_)
                 | Expression::PointerIntoList { .. }
@@ -176,17 +576,24 @@ impl Machine {
                 }
                 Expression::Sequence(steps) => match steps.pop_front() {
                     Some(step) => {
+                        // The last statement replaces the sequence, but it
+                        // is still part of the same instruction.
+                        let address = evaluation.address;
                         if steps.is_empty() {
                             self.call_stack.pop();
                         }
-                        self.solve(step);
+                        self.solve_within(step, address);
                     }
                     None => {
                         warn!("Evaluating empty sequence");
                         self.fetched = Expression::Undefined;
                     }
                 },
-                Expression::Unary { operator, operand } => {
+                Expression::Unary {
+                    operator,
+                    operand,
+                    span,
+                } => {
                     let expr: Expression = std::mem::take(operand);
                     let expr = match expr {
                         Expression::Stub => std::mem::take(&mut self.fetched),
@@ -194,19 +601,20 @@ impl Machine {
                     };
                     if is_value(&expr) {
                         let operator = *operator;
+                        self.span = Some(*span);
                         self.call_stack.pop();
                         self.perform_unary_on_value(operator, expr)
                     } else {
                         trace!("Evaluating operand: {}", expr);
-                        *operand = Box::new(Expression::Stub);
-                        let sub = EvaluationInProgress { expression: expr };
-                        self.call_stack.push(sub);
+                        **operand = Expression::Stub;
+                        self.push(expr, self.current_address());
                     }
                 }
                 Expression::Binary {
                     operator,
                     left: left_operand,
                     right: right_operand,
+                    span,
                 } => {
                     let left: Expression = std::mem::take(left_operand);
                     let left = match left {
@@ -220,29 +628,31 @@ impl Machine {
                     };
                     if is_value(&left) && is_value(&right) {
                         let operator = *operator;
+                        self.span = Some(*span);
                         self.call_stack.pop();
                         self.perform_binary_on_values(operator, left, right)
                     } else if is_value(&left) {
                         trace!("Evaluating RHS: {}", right);
-                        *left_operand = Box::new(left);
-                        *right_operand = Box::new(Expression::Stub);
-                        let sub = EvaluationInProgress { expression: right };
-                        self.call_stack.push(sub);
+                        **left_operand = left;
+                        **right_operand = Expression::Stub;
+                        self.push(right, self.current_address());
                     } else {
                         trace!("Evaluating LHS: {}", left);
-                        *left_operand = Box::new(Expression::Stub);
-                        *right_operand = Box::new(right);
-                        let sub = EvaluationInProgress { expression: left };
-                        self.call_stack.push(sub);
+                        **left_operand = Expression::Stub;
+                        **right_operand = right;
+                        self.push(left, self.current_address());
                     }
                 }
                 Expression::Stub => unreachable!(),
-            },
-            None => (),
+            }
         }
     }
 
     fn solve(&mut self, expression: Expression) {
+        self.solve_within(expression, self.current_address());
+    }
+
+    fn solve_within(&mut self, expression: Expression, address: usize) {
         match expression {
             Expression::Undefined
             | Expression::Number(_)
@@ -255,7 +665,7 @@ impl Machine {
             | Expression::Unary { .. }
             | Expression::Binary { .. } => {
                 trace!("Evaluating {}", expression);
-                self.call_stack.push(EvaluationInProgress { expression });
+                self.push(expression, address);
                 self.fetched = Expression::Undefined;
             }
             Expression::Stub => unreachable!(),
@@ -266,12 +676,14 @@ impl Machine {
         match operator {
             Unary::Fetch => match operand {
                 Expression::Undefined => self.solve(Expression::Undefined),
+                Expression::Number(number) if is_input_address(number) => {
+                    self.read_input(number);
+                }
                 Expression::Number(number) => {
                     // Fetch or evaluate the expression at the given address.
-                    if let Some(address) = self.address_from_number(number) {
-                        self.fetch(address)
-                    } else {
-                        self.solve(Expression::Undefined);
+                    match self.address_from_number(number) {
+                        Ok(address) => self.fetch(address),
+                        Err(error) => self.fail(error),
                     }
                 }
                 Expression::List(elements) => {
@@ -279,32 +691,30 @@ impl Machine {
                     if let Some(element) = elements.into_iter().next() {
                         self.solve(element);
                     } else {
-                        error!("Cannot fetch from empty list");
-                        self.solve(Expression::Undefined);
+                        self.fail(RuntimeErrorKind::EmptyList);
                     }
                 }
                 Expression::PointerIntoList { address, offset } => {
                     // Get element 0 from the slice that begins at offset.
-                    let element = self.copy_element(address, offset);
-                    self.solve(element);
-                }
-                expr => {
-                    warn!("Unimplemented for {}", expr);
-                    self.solve(Expression::Undefined);
+                    match self.copy_element(address, offset) {
+                        Ok(element) => self.solve(element),
+                        Err(error) => self.fail(error),
+                    }
                 }
+                expr => self.unsupported_unary(operator, expr),
             },
             Unary::Signum => match operand {
                 Expression::Undefined => self.solve(Expression::Undefined),
                 Expression::Number(number) => {
-                    let expr = if number == 0.0 || number.is_subnormal() {
-                        Expression::Number(0.0)
+                    if number == 0.0 || number.is_subnormal() {
+                        self.solve(Expression::Number(0.0));
                     } else if number.is_normal() {
-                        Expression::Number(number.signum())
+                        self.solve(Expression::Number(number.signum()));
                     } else {
-                        error!("Abnormal float: {}", number);
-                        Expression::Undefined
-                    };
-                    self.solve(expr);
+                        let error =
+                            RuntimeErrorKind::AbnormalValue { value: number };
+                        self.fail(error);
+                    }
                 }
                 Expression::List(elements) => {
                     // This has to match the behavior below.
@@ -313,13 +723,12 @@ impl Machine {
                 }
                 Expression::PointerIntoList { address, offset } => {
                     // Overload signum to copy lists.
-                    let list = self.copy_list(address, offset);
-                    self.solve(list);
-                }
-                expr => {
-                    warn!("Unimplemented for {}", expr);
-                    self.solve(Expression::Undefined);
+                    match self.copy_list(address, offset) {
+                        Ok(list) => self.solve(list),
+                        Err(error) => self.fail(error),
+                    }
                 }
+                expr => self.unsupported_unary(operator, expr),
             },
             Unary::Neg => match operand {
                 Expression::Undefined => self.solve(Expression::Undefined),
@@ -331,12 +740,12 @@ impl Machine {
                     self.solve(Expression::Number(l.len() as Float))
                 }
                 Expression::PointerIntoList { address, offset } => {
-                    self.solve(self.get_list_len(address, offset))
-                }
-                expr => {
-                    warn!("Unimplemented for {}", expr);
-                    self.solve(Expression::Undefined);
+                    match self.get_list_len(address, offset) {
+                        Ok(len) => self.solve(len),
+                        Err(error) => self.fail(error),
+                    }
                 }
+                expr => self.unsupported_unary(operator, expr),
             },
             Unary::Recip => match operand {
                 Expression::Undefined => self.solve(Expression::Undefined),
@@ -344,10 +753,7 @@ impl Machine {
                     let expr = Expression::Number(1.0 / number);
                     self.solve(expr);
                 }
-                expr => {
-                    warn!("Unimplemented for {}", expr);
-                    self.solve(Expression::Undefined);
-                }
+                expr => self.unsupported_unary(operator, expr),
             },
             Unary::Ceiling => match operand {
                 Expression::Undefined => self.solve(Expression::Undefined),
@@ -355,10 +761,7 @@ impl Machine {
                     let expr = Expression::Number(number.ceil());
                     self.solve(expr);
                 }
-                expr => {
-                    warn!("Unimplemented for {}", expr);
-                    self.solve(Expression::Undefined);
-                }
+                expr => self.unsupported_unary(operator, expr),
             },
             Unary::Floor => match operand {
                 Expression::Undefined => self.solve(Expression::Undefined),
@@ -366,37 +769,76 @@ impl Machine {
                     let expr = Expression::Number(number.floor());
                     self.solve(expr);
                 }
-                expr => {
-                    warn!("Unimplemented for {}", expr);
-                    self.solve(Expression::Undefined);
-                }
+                expr => self.unsupported_unary(operator, expr),
             },
             Unary::Print => match operand {
                 Expression::Undefined => self.solve(Expression::Undefined),
                 Expression::Number(number) => {
-                    if let Some(c) = self.char_from_number(number) {
-                        print!("{}", c);
-                        self.solve(operand);
-                    } else {
-                        self.solve(Expression::Undefined);
+                    match self.char_from_number(number) {
+                        Ok(c) => match self.output.print(c) {
+                            Ok(()) => self.solve(operand),
+                            Err(error) => {
+                                let reason = error.to_string();
+                                self.fail(RuntimeErrorKind::OutputFailed {
+                                    reason,
+                                })
+                            }
+                        },
+                        Err(error) => self.fail(error),
                     }
                 }
-                Expression::List(_) => {
-                    // lists are lazy
-                    unimplemented!()
+                Expression::List(elements) => {
+                    // Lists are lazy, so print each element as it is forced.
+                    let list = Expression::List(elements.clone());
+                    self.print_elements(elements, list);
                 }
-                Expression::PointerIntoList { .. } => {
+                Expression::PointerIntoList { address, offset } => {
                     // This has to match the behavior above.
-                    unimplemented!()
-                }
-                expr => {
-                    warn!("Unimplemented for {}", expr);
-                    self.solve(Expression::Undefined);
+                    match self.get_list(address) {
+                        Ok(elements) => {
+                            let elements =
+                                elements.iter().skip(offset).cloned().collect();
+                            let pointer =
+                                Expression::PointerIntoList { address, offset };
+                            self.print_elements(elements, pointer);
+                        }
+                        Err(error) => self.fail(error),
+                    }
                 }
+                expr => self.unsupported_unary(operator, expr),
             },
         }
     }
 
+    fn print_elements(&mut self, elements: Vec<Expression>, list: Expression) {
+        // Print the elements from left to right and then yield the list,
+        // just like printing a number yields that number. Elements that
+        // are undefined print nothing, and nested lists are printed as well.
+        let span = self.span.unwrap_or_default();
+        let mut steps: std::collections::VecDeque<Expression> = elements
+            .into_iter()
+            .map(|element| Expression::Unary {
+                operator: Unary::Print,
+                operand: Box::new(element),
+                span,
+            })
+            .collect();
+        steps.push_back(list);
+        self.solve(Expression::Sequence(steps));
+    }
+
+    /// Drop the first SKIPPED elements from the list behind a pointer.
+    fn shift_pointer(&mut self, address: usize, offset: usize, skipped: usize) {
+        match offset.checked_add(skipped) {
+            Some(offset) => {
+                self.solve(Expression::PointerIntoList { address, offset })
+            }
+            None => self.fail(RuntimeErrorKind::ValueTooHigh {
+                value: offset as Float + skipped as Float,
+            }),
+        }
+    }
+
     fn perform_binary_on_values(
         &mut self,
         operator: Binary,
@@ -412,47 +854,40 @@ impl Machine {
                         self.solve(Expression::Number(a + b));
                     }
                     Expression::List(mut elements) => {
-                        if let Some(offset) = self.address_from_number(a) {
-                            elements.splice(0..offset, std::iter::empty());
-                            let shifted = Expression::List(elements);
-                            self.solve(shifted);
-                        } else {
-                            self.solve(Expression::Undefined);
+                        match self.address_from_number(a) {
+                            Ok(offset) => {
+                                elements.drain(0..offset.min(elements.len()));
+                                let shifted = Expression::List(elements);
+                                self.solve(shifted);
+                            }
+                            Err(error) => self.fail(error),
                         }
                     }
                     Expression::PointerIntoList { address, offset } => {
                         // Drop the first NUM elements from the list.
-                        if let Some(skipped) = self.address_from_number(a) {
-                            let shifted = Expression::PointerIntoList {
-                                address,
-                                offset: offset + skipped,
-                            };
-                            self.solve(shifted);
-                        } else {
-                            self.solve(Expression::Undefined);
+                        match self.address_from_number(a) {
+                            Ok(skipped) => {
+                                self.shift_pointer(address, offset, skipped)
+                            }
+                            Err(error) => self.fail(error),
                         }
                     }
-                    expr => {
-                        warn!("Unimplemented for {}", expr);
-                        self.solve(Expression::Undefined);
-                    }
+                    expr => self.unsupported_binary(operator, expr),
                 },
                 Expression::List(mut elements) => match right {
                     Expression::Undefined => self.solve(Expression::Undefined),
                     Expression::Number(number) => {
                         // Drop the first NUM elements from the list.
-                        if let Some(offset) = self.address_from_number(number) {
-                            elements.splice(0..offset, std::iter::empty());
-                            let shifted = Expression::List(elements);
-                            self.solve(shifted);
-                        } else {
-                            self.solve(Expression::Undefined);
+                        match self.address_from_number(number) {
+                            Ok(offset) => {
+                                elements.drain(0..offset.min(elements.len()));
+                                let shifted = Expression::List(elements);
+                                self.solve(shifted);
+                            }
+                            Err(error) => self.fail(error),
                         }
                     }
-                    expr => {
-                        warn!("Unimplemented for {}", expr);
-                        self.solve(Expression::Undefined);
-                    }
+                    expr => self.unsupported_binary(operator, expr),
                 },
                 Expression::PointerIntoList { address, offset } => {
                     match right {
@@ -461,28 +896,17 @@ impl Machine {
                         }
                         Expression::Number(number) => {
                             // Drop the first NUM elements from the list.
-                            if let Some(skipped) =
-                                self.address_from_number(number)
-                            {
-                                let shifted = Expression::PointerIntoList {
-                                    address,
-                                    offset: offset + skipped,
-                                };
-                                self.solve(shifted);
-                            } else {
-                                self.solve(Expression::Undefined);
+                            match self.address_from_number(number) {
+                                Ok(skipped) => {
+                                    self.shift_pointer(address, offset, skipped)
+                                }
+                                Err(error) => self.fail(error),
                             }
                         }
-                        expr => {
-                            warn!("Unimplemented for {}", expr);
-                            self.solve(Expression::Undefined);
-                        }
+                        expr => self.unsupported_binary(operator, expr),
                     }
                 }
-                expr => {
-                    warn!("Unimplemented for {}", expr);
-                    self.solve(Expression::Undefined);
-                }
+                expr => self.unsupported_binary(operator, expr),
             },
             Binary::Mult => match left {
                 Expression::Undefined => self.solve(Expression::Undefined),
@@ -491,24 +915,19 @@ impl Machine {
                     Expression::Number(b) => {
                         self.solve(Expression::Number(a * b));
                     }
-                    expr => {
-                        warn!("Unimplemented for {}", expr);
-                        self.solve(Expression::Undefined);
-                    }
+                    expr => self.unsupported_binary(operator, expr),
                 },
-                expr => {
-                    warn!("Unimplemented for {}", expr);
-                    self.solve(Expression::Undefined);
-                }
+                expr => self.unsupported_binary(operator, expr),
             },
             Binary::Assign => match left {
                 Expression::Undefined => self.solve(Expression::Undefined),
                 Expression::Number(number) => {
                     // Assign a value to a register.
-                    if let Some(address) = self.address_from_number(number) {
-                        self.store(address, right);
-                    } else {
-                        self.solve(Expression::Undefined);
+                    match self.address_from_number(number) {
+                        Ok(address) => {
+                            self.store(address, right);
+                        }
+                        Err(error) => self.fail(error),
                     }
                 }
                 Expression::List(mut elements) => {
@@ -519,13 +938,21 @@ impl Machine {
                     self.solve(Expression::List(elements));
                 }
                 Expression::PointerIntoList { address, offset } => {
-                    self.store_element(address, offset, right);
-                    self.solve(Expression::PointerIntoList { address, offset });
-                }
-                expr => {
-                    warn!("Unimplemented for {}", expr);
-                    self.solve(Expression::Undefined);
+                    match self.store_element(address, offset, right) {
+                        Ok(()) => {
+                            let pointer =
+                                Expression::PointerIntoList { address, offset };
+                            self.solve(pointer);
+                        }
+                        Err(
+                            error @ RuntimeErrorKind::ListLimitExceeded {
+                                ..
+                            },
+                        ) => self.halt(error),
+                        Err(error) => self.fail(error),
+                    }
                 }
+                expr => self.unsupported_binary(operator, expr),
             },
             Binary::CallWith => match left {
                 Expression::Undefined => self.solve(Expression::Undefined),
@@ -533,132 +960,218 @@ impl Machine {
                     self.set_called_with(Expression::Undefined);
                     self.instruction_address = 0;
                     // Evaluate the expression at the given address...
-                    if let Some(address) = self.address_from_number(number) {
-                        self.fetch(address);
-                    } else {
-                        self.solve(Expression::Undefined);
+                    match self.address_from_number(number) {
+                        Ok(address) => {
+                            self.fetch(address);
+                        }
+                        Err(error) => self.fail(error),
                     }
                     // ...after storing the argument list.
                     self.set_called_with(right);
                 }
-                expr => {
-                    warn!("Unimplemented for {}", expr);
-                    self.solve(Expression::Undefined);
-                }
+                expr => self.unsupported_binary(operator, expr),
             },
-            Binary::Abort => {
-                println!("Aborting program");
-                abort()
-            }
+            Binary::Abort => self.halt(RuntimeErrorKind::Aborted),
         }
     }
 
-    fn copy_list(&self, address: usize, offset: usize) -> Expression {
-        match self.tape.get(address) {
-            Some(Expression::List(elements)) => Expression::List(
-                elements.iter().skip(offset).cloned().collect(),
-            ),
-            Some(expr) => {
-                error!("Cannot copy non-list: {}", expr);
-                Expression::Undefined
+    fn read_input(&mut self, number: Float) {
+        let address = number.round();
+        if address == INPUT_CHAR_ADDRESS {
+            match self.input.read_char() {
+                Ok(Some(c)) => {
+                    trace!("Read input {:?}", c);
+                    self.solve(Expression::Number(c as u32 as Float));
+                }
+                Ok(None) => {
+                    trace!("Reached end of input");
+                    self.solve(Expression::Undefined);
+                }
+                Err(error) => {
+                    let reason = error.to_string();
+                    self.fail(RuntimeErrorKind::InputFailed { reason });
+                }
             }
-            None => {
-                error!("Cannot copy out of bounds: {}", address);
-                Expression::Undefined
+        } else {
+            match self.input.read_word() {
+                Ok(Some(word)) => match word.parse() {
+                    Ok(number) => {
+                        trace!("Read input {}", number);
+                        self.solve(Expression::Number(number));
+                    }
+                    Err(_) => {
+                        self.fail(RuntimeErrorKind::InvalidNumberInput {
+                            word,
+                        });
+                    }
+                },
+                Ok(None) => {
+                    trace!("Reached end of input");
+                    self.solve(Expression::Undefined);
+                }
+                Err(error) => {
+                    let reason = error.to_string();
+                    self.fail(RuntimeErrorKind::InputFailed { reason });
+                }
             }
         }
     }
-    fn get_list_len(&self, address: usize, offset: usize) -> Expression {
-        match self.tape.get(address) {
-            Some(Expression::List(elements)) => Expression::Number(
-                elements.len().saturating_sub(offset) as Float,
-            ),
-            Some(expr) => {
-                error!("only lists have lengths: {}", expr);
-                Expression::Undefined
-            }
-            None => {
-                error!("Cannot copy out of bounds: {}", address);
-                Expression::Undefined
-            }
-        }
+
+    fn copy_list(
+        &self,
+        address: usize,
+        offset: usize,
+    ) -> Result<Expression, RuntimeErrorKind> {
+        let elements = self.get_list(address)?;
+        let list =
+            Expression::List(elements.iter().skip(offset).cloned().collect());
+        Ok(list)
     }
 
-    fn copy_element(&self, address: usize, offset: usize) -> Expression {
-        match self.tape.get(address) {
-            Some(Expression::List(elements)) => {
-                let element = elements.iter().skip(offset).next().cloned();
-                match element {
-                    Some(element) => element,
-                    None => {
-                        error!(
-                            "Index out of bounds: {} in {} at {}",
-                            offset,
-                            Expression::List(elements.to_vec()),
-                            address
-                        );
-                        Expression::Undefined
-                    }
-                }
-            }
-            Some(expr) => {
-                error!("Cannot copy element from non-list: {}", expr);
-                Expression::Undefined
-            }
-            None => {
-                error!("Cannot copy out of bounds: {}", address);
-                Expression::Undefined
-            }
-        }
+    fn get_list_len(
+        &self,
+        address: usize,
+        offset: usize,
+    ) -> Result<Expression, RuntimeErrorKind> {
+        let elements = self.get_list(address)?;
+        let len = elements.len().saturating_sub(offset);
+        Ok(Expression::Number(len as Float))
     }
 
-    fn store_element(&mut self, address: usize, offset: usize, v: Expression) {
+    fn copy_element(
+        &self,
+        address: usize,
+        offset: usize,
+    ) -> Result<Expression, RuntimeErrorKind> {
+        let elements = self.get_list(address)?;
+        elements
+            .get(offset)
+            .cloned()
+            .ok_or(RuntimeErrorKind::ElementOutOfBounds { address, offset })
+    }
+
+    fn store_element(
+        &mut self,
+        address: usize,
+        offset: usize,
+        v: Expression,
+    ) -> Result<(), RuntimeErrorKind> {
+        let max_list_len = self.limits.max_list_len;
         match self.tape.get_mut(address) {
             Some(Expression::List(elements)) => {
-                let element = elements.iter_mut().skip(offset).next();
-                match element {
+                match elements.get_mut(offset) {
                     Some(element) => *element = v,
-                    None => elements.push(v),
+                    None => match max_list_len {
+                        Some(limit) if elements.len() >= limit => {
+                            return Err(RuntimeErrorKind::ListLimitExceeded {
+                                address,
+                                limit,
+                            });
+                        }
+                        _ => elements.push(v),
+                    },
                 }
+                self.event = Some(Event::Write { address });
+                Ok(())
             }
-            Some(expr) => {
-                error!("Cannot store in element of non-list: {}", expr);
-            }
-            None => {
-                error!("Cannot store out of bounds: {}", address);
-            }
+            Some(expr) => Err(RuntimeErrorKind::NotAList {
+                address,
+                got: expr.clone(),
+            }),
+            None => Err(RuntimeErrorKind::NotAList {
+                address,
+                got: Expression::Undefined,
+            }),
         }
     }
 
-    fn address_from_number(&self, number: Float) -> Option<usize> {
-        let address = self.u32_from_number(number)?;
-        Some(address as usize)
+    fn get_list(
+        &self,
+        address: usize,
+    ) -> Result<&[Expression], RuntimeErrorKind> {
+        match self.tape.get(address) {
+            Some(Expression::List(elements)) => Ok(elements),
+            Some(expr) => Err(RuntimeErrorKind::NotAList {
+                address,
+                got: expr.clone(),
+            }),
+            None => Err(RuntimeErrorKind::NotAList {
+                address,
+                got: Expression::Undefined,
+            }),
+        }
     }
 
-    fn char_from_number(&self, number: Float) -> Option<char> {
+    fn address_from_number(
+        &self,
+        number: Float,
+    ) -> Result<usize, RuntimeErrorKind> {
+        if number.is_normal() {
+            if number < 0.5 {
+                Ok(0)
+            } else if number < usize::MAX as f64 {
+                Ok(number as usize)
+            } else {
+                Err(RuntimeErrorKind::ValueTooHigh { value: number })
+            }
+        } else if number == 0.0 {
+            Ok(0)
+        } else {
+            Err(RuntimeErrorKind::AbnormalValue { value: number })
+        }
+    }
+
+    fn char_from_number(
+        &self,
+        number: Float,
+    ) -> Result<char, RuntimeErrorKind> {
         let codepoint: u32 = self.u32_from_number(number)?;
-        char::from_u32(codepoint).or_else(|| {
-            error!("Codepoint is invalid: {}", number);
-            None
-        })
+        char::from_u32(codepoint)
+            .ok_or(RuntimeErrorKind::InvalidCodepoint { value: number })
     }
 
-    fn u32_from_number(&self, number: Float) -> Option<u32> {
+    fn u32_from_number(&self, number: Float) -> Result<u32, RuntimeErrorKind> {
         if number.is_normal() {
             if number < 0.5 {
-                Some(0)
+                Ok(0)
             } else if number < u32::MAX as f64 {
-                Some(number as u32)
+                Ok(number as u32)
             } else {
-                error!("Value is too high: {}", number);
-                None
+                Err(RuntimeErrorKind::ValueTooHigh { value: number })
             }
         } else if number == 0.0 {
-            Some(0)
+            Ok(0)
         } else {
-            error!("Value is abnormal: {}", number);
-            None
+            Err(RuntimeErrorKind::AbnormalValue { value: number })
+        }
+    }
+}
+
+/// Whether the value is deparsed as an expression that evaluates to it,
+/// rather than as a literal, so that it would become an instruction when
+/// loaded.
+fn has_no_literal(expression: &Expression) -> bool {
+    match expression {
+        Expression::Number(number) => {
+            !number.is_finite() || number.is_sign_negative()
         }
+        Expression::PointerIntoList { .. } => true,
+        _ => false,
+    }
+}
+
+fn contains_undefined(expression: &Expression) -> bool {
+    match expression {
+        Expression::Undefined => true,
+        Expression::List(elements) => elements.iter().any(contains_undefined),
+        Expression::Sequence(statements) => {
+            statements.iter().any(contains_undefined)
+        }
+        Expression::Unary { operand, .. } => contains_undefined(operand),
+        Expression::Binary { left, right, .. } => {
+            contains_undefined(left) || contains_undefined(right)
+        }
+        _ => false,
     }
 }
 
@@ -671,6 +1184,195 @@ fn is_value(expression: &Expression) -> bool {
         Expression::Sequence(_) => false,
         Expression::Unary { .. } => false,
         Expression::Binary { .. } => false,
-        Expression::Stub { .. } => false,
+        Expression::Stub => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(source: &str) -> Machine {
+        Machine::create(crate::compile(source).unwrap())
+    }
+
+    #[test]
+    fn runtime_error_has_address_and_span() {
+        let mut machine = machine("1 .. 2/3\n2 .. 0 - 4");
+        let error = machine.evaluate(1).unwrap_err();
+        assert!(matches!(
+            error.kind,
+            RuntimeErrorKind::IllegalWriteToAddressZero
+        ));
+        assert_eq!(error.address, 2);
+        let span = error.span.unwrap();
+        assert_eq!((span.line, span.column), (2, 8));
+    }
+
+    #[test]
+    fn interrupt_reports_the_current_address() {
+        let mut machine = machine("1 .. 4 + 3/0 + 5 + 6\n3 .. 7 + 1");
+        let interrupt = Arc::new(AtomicBool::new(false));
+        machine.set_interrupt(interrupt.clone());
+        machine.start(1);
+        while machine.step() != Some(Event::Call { address: 3 }) {}
+        // Interrupt once instruction 3 has returned to 1.
+        while machine.call_stack().last().unwrap().address == 3 {
+            machine.step();
+        }
+        interrupt.store(true, Ordering::SeqCst);
+        machine.run_to_end();
+        let error = machine.finish().unwrap_err();
+        assert!(matches!(error.kind, RuntimeErrorKind::Interrupted { .. }));
+        assert_eq!(error.address, 1);
+    }
+
+    #[test]
+    fn machine_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Machine>();
+    }
+
+    #[test]
+    fn output_and_input_buffers() {
+        let mut machine = machine("1 .. *./. /.*-1./ + 1 ./ .. *-2");
+        let output = crate::OutputBuffer::new();
+        machine.set_output(Box::new(output.clone()));
+        machine.set_input(Box::new(crate::InputBuffer::new("A 42")));
+        let result = machine.evaluate(1).unwrap();
+        assert_eq!(output.contents(), "B");
+        assert!(matches!(result, Expression::Number(number) if number == 42.0));
+    }
+
+    #[test]
+    fn dropping_more_elements_than_a_list_has_leaves_it_empty() {
+        for source in ["1 .. 5 + /.1..2./", "1 .. /.1..2./ + 5"] {
+            let result = machine(source).evaluate(1).unwrap();
+            assert!(matches!(result, Expression::List(elements)
+                if elements.is_empty()));
+        }
+    }
+
+    #[test]
+    fn shifting_a_pointer_too_far_is_an_error() {
+        let mut machine = machine(
+            "1 .. 100 - /.1..2./ .. 10000000000000000000 \
+             + /.10000000000000000000 + *100./",
+        );
+        let error = machine.evaluate(1).unwrap_err();
+        assert!(matches!(error.kind, RuntimeErrorKind::ValueTooHigh { .. }));
+    }
+
+    fn limited(source: &str, limits: Limits) -> RuntimeErrorKind {
+        let mut machine = machine(source);
+        machine.set_limits(limits);
+        machine.evaluate(1).unwrap_err().kind
+    }
+
+    #[test]
+    fn step_limit() {
+        let limits = Limits {
+            max_steps: Some(2),
+            ..Limits::default()
+        };
+        let kind = limited("1 .. 1 + 1 + 1 + 1", limits);
+        assert!(matches!(kind, RuntimeErrorKind::StepLimitExceeded { .. }));
+    }
+
+    #[test]
+    fn tape_limit() {
+        let limits = Limits {
+            max_tape_size: Some(2),
+            ..Limits::default()
+        };
+        let kind = limited("1 .. 10 - 1 .. 11 - 2", limits);
+        assert!(matches!(
+            kind,
+            RuntimeErrorKind::TapeLimitExceeded {
+                address: 11,
+                limit: 2
+            }
+        ));
+    }
+
+    #[test]
+    fn call_depth_limit() {
+        let limits = Limits {
+            max_call_depth: Some(10),
+            ..Limits::default()
+        };
+        let kind = limited("1 .. 1 + *2\n2 .. 1 + *1", limits);
+        assert!(matches!(kind, RuntimeErrorKind::CallDepthExceeded { .. }));
+    }
+
+    #[test]
+    fn list_limit() {
+        let limits = Limits {
+            max_list_len: Some(3),
+            ..Limits::default()
+        };
+        let kind = limited(
+            "1 .. 100 - /.1..2./ \
+             .. /./.*100./ + 2./ - 7 \
+             .. /./.*100./ + 3./ - 8",
+            limits,
+        );
+        assert!(matches!(
+            kind,
+            RuntimeErrorKind::ListLimitExceeded {
+                address: 100,
+                limit: 3
+            }
+        ));
+    }
+
+    #[test]
+    fn other_negative_addresses_are_the_call_argument() {
+        let mut machine = machine("1 .. 2/7\n2 .. *-3");
+        let result = machine.evaluate(1).unwrap();
+        assert!(matches!(result, Expression::Number(number) if number == 7.0));
+    }
+
+    #[test]
+    fn dump_restores_the_state() {
+        let mut machine = machine(
+            "1 .. 20 - /.1..2..3./ .. 10 - -5 .. 11 - 1 + *20 .. 3/7\n\
+             2 .. /.*10./ + *0\n\
+             3 .. 2/7",
+        );
+        machine.evaluate(1).unwrap();
+        let before = machine.evaluate(3).unwrap();
+        let dump = machine.dump();
+        let mut restored = Machine::create(Vec::new());
+        restored.load_dump(crate::compile(&dump).unwrap()).unwrap();
+        assert!(!restored.tape().is_defined(0));
+        assert_eq!(restored.dump(), dump);
+        let after = restored.evaluate(3).unwrap();
+        assert!(matches!(before, Expression::Number(n) if n == 2.0));
+        assert!(matches!(after, Expression::Number(n) if n == 2.0));
+        let element = restored
+            .evaluate_expression(crate::compile_expression("**11").unwrap());
+        assert!(matches!(element, Ok(Expression::Number(n)) if n == 2.0));
+    }
+
+    #[test]
+    fn update_does_not_run_the_setup() {
+        let mut machine = Machine::create(Vec::new());
+        machine.update(crate::compile("0 .. 5 - 1").unwrap());
+        assert!(machine.tape().is_defined(0));
+        assert!(!machine.tape().is_defined(5));
+    }
+
+    #[test]
+    fn dump_leaves_out_undefined_elements() {
+        let mut machine =
+            machine("1 .. 20 - /.1..2./ .. /./.*20./ + 1./ - *99 .. 30 - 4");
+        machine.evaluate(1).unwrap();
+        let dump = machine.dump();
+        assert!(dump.contains("(20 is left out"));
+        let mut restored = Machine::create(Vec::new());
+        restored.load_dump(crate::compile(&dump).unwrap()).unwrap();
+        assert!(!restored.tape().is_defined(20));
+        assert!(restored.tape().is_defined(30));
     }
 }
@@ -6,20 +6,33 @@
 
 #![forbid(unsafe_code)]
 
-mod common;
-mod lexer;
-mod machine;
-mod parser;
+mod dap;
+mod debug;
+mod helper;
+mod lsp;
+mod protocol;
 
-use crate::common::*;
-use crate::machine::Machine;
+use crate::helper::ReplHelper;
+use numpad::common::*;
+use numpad::lexer::{self, Token};
+use numpad::lint::{self, Level, Lint};
+use numpad::{deparse, Diagnostic, Limits, Machine, Tape};
 
 use clap::Parser;
-use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 #[derive(Debug, clap::Parser)]
 #[clap(version, propagate_version = true)]
+#[clap(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// One or more Numpad source files
     #[clap(value_parser)]
     filepaths: Vec<std::path::PathBuf>,
@@ -39,6 +52,118 @@ struct Cli {
     /// Enable the REPL
     #[clap(short, long)]
     repl: bool,
+
+    #[clap(flatten)]
+    evaluation: Evaluation,
+
+    /// Write the machine state to this file when done, as source code
+    #[clap(long)]
+    dump: Option<std::path::PathBuf>,
+}
+
+/// How runtime errors and resource limits are handled, shared by every
+/// command that runs a program.
+#[derive(Debug, clap::Args)]
+struct Evaluation {
+    /// Stop evaluation at the first runtime error
+    #[clap(long)]
+    strict: bool,
+
+    /// Stop evaluation after this many steps
+    #[clap(long)]
+    max_steps: Option<u64>,
+
+    /// Stop evaluation when more than this many addresses would be defined
+    #[clap(long)]
+    max_tape_size: Option<usize>,
+
+    /// Stop evaluation when expressions are nested deeper than this
+    #[clap(long)]
+    max_call_depth: Option<usize>,
+
+    /// Stop evaluation when a list would grow longer than this
+    #[clap(long)]
+    max_list_len: Option<usize>,
+}
+
+impl Evaluation {
+    fn configure(&self, machine: &mut Machine) {
+        machine.set_lenient(!self.strict);
+        machine.set_limits(Limits {
+            max_steps: self.max_steps,
+            max_tape_size: self.max_tape_size,
+            max_call_depth: self.max_call_depth,
+            max_list_len: self.max_list_len,
+        });
+    }
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Rewrite source files in the canonical layout
+    Fmt {
+        /// One or more Numpad source files
+        #[clap(value_parser, required = true)]
+        filepaths: Vec<std::path::PathBuf>,
+
+        /// Only check whether the files are formatted, without changing them
+        #[clap(long)]
+        check: bool,
+    },
+    /// Print the shortest equivalent source code
+    Minify {
+        /// A Numpad source file
+        #[clap(value_parser)]
+        filepath: std::path::PathBuf,
+    },
+    /// Look for likely mistakes without running the program
+    Check {
+        /// One or more Numpad source files
+        #[clap(value_parser, required = true)]
+        filepaths: Vec<std::path::PathBuf>,
+
+        /// Ignore this lint
+        #[clap(short = 'A', long, value_name = "LINT")]
+        allow: Vec<Lint>,
+
+        /// Report this lint as a warning
+        #[clap(short = 'W', long, value_name = "LINT")]
+        warn: Vec<Lint>,
+
+        /// Report this lint as an error
+        #[clap(short = 'D', long, value_name = "LINT")]
+        deny: Vec<Lint>,
+    },
+    /// Print which instructions fetch, call or assign which addresses
+    Graph {
+        /// A Numpad source file
+        #[clap(value_parser)]
+        filepath: std::path::PathBuf,
+
+        /// The output format
+        #[clap(long, value_enum, default_value = "dot")]
+        format: GraphFormat,
+    },
+    /// Step through a program with breakpoints and watchpoints
+    Debug {
+        /// A Numpad source file
+        #[clap(value_parser)]
+        filepath: std::path::PathBuf,
+
+        #[clap(flatten)]
+        evaluation: Evaluation,
+    },
+    /// Serve the Debug Adapter Protocol over stdin and stdout
+    Dap,
+    /// Serve the Language Server Protocol over stdin and stdout
+    Lsp,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum GraphFormat {
+    /// The DOT language of Graphviz
+    Dot,
+    Json,
 }
 
 fn main() -> Result<(), anyhow::Error> {
@@ -48,22 +173,81 @@ fn main() -> Result<(), anyhow::Error> {
         .quiet(!args.verbose)
         .verbosity(args.log_level.unwrap_or(log::Level::Trace))
         .init()?;
-    let mut rl = DefaultEditor::new()?;
 
-    let ref mut machine = Machine::create(vec![Instruction {
+    if let Some(command) = args.command {
+        return match command {
+            Command::Fmt { filepaths, check } => fmt(&filepaths, check),
+            Command::Minify { filepath } => minify(&filepath),
+            Command::Check {
+                filepaths,
+                allow,
+                warn,
+                deny,
+            } => {
+                let mut config = lint::Config::default();
+                for (lints, level) in [
+                    (allow, Level::Allow),
+                    (warn, Level::Warn),
+                    (deny, Level::Deny),
+                ] {
+                    for lint in lints {
+                        config.set_level(lint, level);
+                    }
+                }
+                check(&filepaths, &config)
+            }
+            Command::Graph { filepath, format } => graph(&filepath, format),
+            Command::Debug {
+                filepath,
+                evaluation,
+            } => debug(&filepath, &evaluation),
+            Command::Dap => dap::serve(),
+            Command::Lsp => lsp::serve(),
+        };
+    }
+
+    let mut rl = Editor::<ReplHelper, DefaultHistory>::new()?;
+    rl.set_helper(Some(ReplHelper::default()));
+
+    let machine = &mut Machine::create(vec![Instruction {
         label: 1,
         expression: Expression::Number(0.0),
+        span: Span::default(),
     }]);
-    let filepath = &args.filepaths.get(0);
+    args.evaluation.configure(machine);
+    let initial = machine.tape().clone();
+
+    // The first Ctrl-C interrupts the evaluation, a second one before it is
+    // handled (for example while waiting for input) exits.
+    let interrupt = Arc::new(AtomicBool::new(false));
+    machine.set_interrupt(interrupt.clone());
+    ctrlc::set_handler(move || {
+        if interrupt.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+    })?;
+    let filepath = args.filepaths.first();
 
     let repl = args.repl | filepath.is_none();
 
+    // A runtime error in the file still leaves the REPL and the dump to run,
+    // but makes the process exit with an error afterwards.
+    let mut is_failed = false;
     if let Some(filepath) = filepath {
-        let source = std::fs::read_to_string(&filepath)?;
-        let tokens = lexer::lex(&source)?;
-        let instructions = parser::parse(tokens)?;
-        let output = evaluate(instructions, machine)?;
-        println!("Output: {}", output);
+        let source = std::fs::read_to_string(filepath)?;
+        let (instructions, diagnostics) = numpad::compile_all(&source);
+        if !diagnostics.is_empty() {
+            let path = filepath.to_string_lossy();
+            eprint!("{}", render_all(&diagnostics, &source, Some(&path)));
+            std::process::exit(1);
+        }
+        match evaluate(instructions, machine) {
+            Ok(output) => println!("Output: {}", output),
+            Err(error) => {
+                eprintln!("Error: {}", error);
+                is_failed = true;
+            }
+        }
     }
 
     if repl {
@@ -71,19 +255,47 @@ fn main() -> Result<(), anyhow::Error> {
             println!("No previous history.");
         }
 
-        let ref mut read = String::new();
+        let read = &mut String::new();
+        let session = &mut Session {
+            history: Vec::new(),
+            checkpoints: BTreeMap::new(),
+            initial: initial.clone(),
+            state_path: args.dump.clone().unwrap_or_else(|| "dump.num".into()),
+        };
         'exit: loop {
             // read
             read.clear();
             'read: loop {
-                let mut readline = rl.readline("| ")?;
+                if let Some(helper) = rl.helper_mut() {
+                    helper.tape = machine.tape().clone();
+                    helper.pending = read.clone();
+                }
+                let mut readline = match rl.readline("| ") {
+                    Ok(readline) => readline,
+                    Err(ReadlineError::Interrupted) => {
+                        read.clear();
+                        continue;
+                    }
+                    Err(ReadlineError::Eof) => break 'exit,
+                    Err(error) => Err(error)?,
+                };
                 rl.add_history_entry(readline.as_str())?;
 
                 readline.push('\n');
                 match readline.as_bytes() {
-                    [b'0'..=b'9', ..] | [b'.', b'.', ..] => {}
-                    [b'-', b'-', b'-', b'-', ..] => break 'exit,
+                    [b'-', b'-', b'-', command @ ..] => {
+                        let command = String::from_utf8_lossy(command);
+                        if run_command(command.trim(), session, machine) {
+                            break 'exit;
+                        }
+                        continue;
+                    }
                     [b'\n', ..] => break 'read,
+                    _ if is_expression(&readline) => {
+                        calculate(&readline, machine);
+                        continue;
+                    }
+                    [b'0'..=b'9', ..] | [b'.', b'.', ..] => {}
                     _ => {
                         println!("Invalid starting character");
                         continue;
@@ -92,28 +304,29 @@ fn main() -> Result<(), anyhow::Error> {
                 read.push_str(&readline)
             }
             // evaluate
-            let tokens = match lexer::lex(&read) {
-                Ok(t) => t,
-                Err(e) => {
-                    println!("Bad Input\nError :: {e}");
-                    continue;
-                }
-            };
-            let instructions = match parser::parse(tokens) {
-                Ok(t) => t,
-                Err(e) => {
-                    println!("Bad Input\nError :: {e}");
-                    continue;
-                }
-            };
+            let (instructions, diagnostics) = numpad::compile_all(read);
+            if !diagnostics.is_empty() {
+                print!("{}", render_all(&diagnostics, read, None));
+                continue;
+            }
+            session.history.push(machine.tape().clone());
             // print
-            let output = evaluate(instructions, machine)?;
-            println!("Output: {}", output);
+            match evaluate(instructions, machine) {
+                Ok(output) => println!("Output: {}", output),
+                Err(e) => println!("Runtime error\nError :: {e}"),
+            }
             // loop
         }
 
         rl.save_history("history.txt")?;
     }
+
+    if let Some(path) = args.dump {
+        std::fs::write(path, machine.dump())?;
+    }
+    if is_failed {
+        std::process::exit(1);
+    }
     Ok(())
 }
 
@@ -122,6 +335,328 @@ fn evaluate(
     machine: &mut Machine,
 ) -> Result<Expression, anyhow::Error> {
     machine.update(program);
-    let answer = machine.evaluate_until_finished(1);
+    let answer = machine.evaluate(1)?;
     Ok(answer)
 }
+
+/// Whether the line is an expression on its own, rather than the start or
+/// continuation of an instruction. A lone number is the label of an
+/// instruction whose statements follow on the next lines.
+fn is_expression(line: &str) -> bool {
+    let tokens: Vec<Token> = lexer::tokens(line)
+        .filter(|(token, _, slice)| match token {
+            Token::Comment | Token::Enter => false,
+            Token::Error => !slice.trim().is_empty(),
+            _ => true,
+        })
+        .map(|(token, _, _)| token)
+        .collect();
+    !matches!(
+        tokens.as_slice(),
+        [] | [Token::Separator, ..]
+            | [Token::Number]
+            | [Token::Number, Token::Separator, ..]
+    )
+}
+
+/// Evaluate an expression line at once, without changing the tape.
+fn calculate(line: &str, machine: &mut Machine) {
+    match numpad::compile_expression(line) {
+        Ok(expression) => match machine.evaluate_expression(expression) {
+            Ok(output) => println!("Output: {}", output),
+            Err(e) => println!("Runtime error\nError :: {e}"),
+        },
+        Err(diagnostics) => print!("{}", render_all(&diagnostics, line, None)),
+    }
+}
+
+fn fmt(filepaths: &[std::path::PathBuf], check: bool) -> anyhow::Result<()> {
+    let mut is_unformatted = false;
+    for filepath in filepaths {
+        let source = std::fs::read_to_string(filepath)?;
+        let path = filepath.to_string_lossy();
+        let formatted = match numpad::format::format(&source) {
+            Ok(formatted) => formatted,
+            Err(diagnostics) => {
+                eprint!("{}", render_all(&diagnostics, &source, Some(&path)));
+                std::process::exit(1);
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("Would reformat: {}", path);
+            is_unformatted = true;
+        } else {
+            std::fs::write(filepath, formatted)?;
+        }
+    }
+    if is_unformatted {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn minify(filepath: &std::path::Path) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(filepath)?;
+    match numpad::minify::minify(&source) {
+        Ok(minified) => print!("{}", minified),
+        Err(diagnostics) => {
+            let path = filepath.to_string_lossy();
+            eprint!("{}", render_all(&diagnostics, &source, Some(&path)));
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+fn check(
+    filepaths: &[std::path::PathBuf],
+    config: &lint::Config,
+) -> anyhow::Result<()> {
+    let mut warnings = 0;
+    let mut errors = 0;
+    for filepath in filepaths {
+        let source = std::fs::read_to_string(filepath)?;
+        let path = filepath.to_string_lossy();
+        let (_, diagnostics) = numpad::compile_all(&source);
+        if !diagnostics.is_empty() {
+            for diagnostic in &diagnostics {
+                eprintln!("{}", diagnostic.render(&source, Some(&path)));
+            }
+            errors += diagnostics.len();
+            continue;
+        }
+        for finding in lint::check(&source, config) {
+            let diagnostic = &finding.diagnostic;
+            if finding.level == Level::Deny {
+                eprintln!("{}", diagnostic.render(&source, Some(&path)));
+                errors += 1;
+            } else {
+                let warning = diagnostic.render_warning(&source, Some(&path));
+                eprintln!("{}", warning);
+                warnings += 1;
+            }
+        }
+    }
+    match warnings {
+        0 => (),
+        1 => eprintln!("warning: 1 warning emitted"),
+        n => eprintln!("warning: {n} warnings emitted"),
+    }
+    match errors {
+        0 => Ok(()),
+        n => {
+            match n {
+                1 => eprintln!("error: aborting due to previous error"),
+                n => eprintln!("error: aborting due to {n} errors"),
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+fn graph(
+    filepath: &std::path::Path,
+    format: GraphFormat,
+) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(filepath)?;
+    let (instructions, diagnostics) = numpad::compile_all(&source);
+    if !diagnostics.is_empty() {
+        let path = filepath.to_string_lossy();
+        eprint!("{}", render_all(&diagnostics, &source, Some(&path)));
+        std::process::exit(1);
+    }
+    let graph = numpad::graph::graph(&instructions);
+    match format {
+        GraphFormat::Dot => print!("{}", graph.to_dot()),
+        GraphFormat::Json => println!("{:#}", graph.to_json()),
+    }
+    Ok(())
+}
+
+fn debug(
+    filepath: &std::path::Path,
+    evaluation: &Evaluation,
+) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(filepath)?;
+    let (instructions, diagnostics) = numpad::compile_all(&source);
+    if !diagnostics.is_empty() {
+        let path = filepath.to_string_lossy();
+        eprint!("{}", render_all(&diagnostics, &source, Some(&path)));
+        std::process::exit(1);
+    }
+    let mut machine = Machine::create(instructions);
+    evaluation.configure(&mut machine);
+    debug::debug(&source, machine)
+}
+
+/// State of the REPL that is kept between submissions.
+struct Session {
+    /// Snapshots of the tape from before each change, for undo.
+    history: Vec<Tape>,
+    checkpoints: BTreeMap<usize, Tape>,
+    /// The tape before any source code was loaded.
+    initial: Tape,
+    /// Where the state is saved to and loaded from.
+    state_path: std::path::PathBuf,
+}
+
+const REPL_COMMANDS: &str = "\
+---        Show this list of commands
+----       Exit
+---N       Evaluate address N
+---N-      Clear address N
+---*       List the defined addresses
+---*N      Show address N
+---.       Save the state
+---.. FILE Load source code without evaluating it, by default the state
+---+N      Save checkpoint N
+---/N      Roll back to checkpoint N
+---/       Undo the last change
+---//      Reset the machine";
+
+/// Run a REPL command, given the text after the three dashes. Returns true
+/// if the REPL should exit.
+fn run_command(
+    command: &str,
+    session: &mut Session,
+    machine: &mut Machine,
+) -> bool {
+    let number = |text: &str| text.trim().parse::<usize>().ok();
+    match command {
+        "" => println!("{}", REPL_COMMANDS),
+        _ if command.starts_with('-') => return true,
+        "." => {
+            let path = &session.state_path;
+            match std::fs::write(path, machine.dump()) {
+                Ok(()) => println!("Saved state to {}", path.display()),
+                Err(error) => {
+                    println!("Failed to save {}: {}", path.display(), error)
+                }
+            }
+        }
+        _ if command.starts_with("..") => {
+            let path = match command[2..].trim() {
+                "" => session.state_path.clone(),
+                path => path.into(),
+            };
+            let source = match std::fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(error) => {
+                    println!("Failed to load {}: {}", path.display(), error);
+                    return false;
+                }
+            };
+            let (instructions, diagnostics) = numpad::compile_all(&source);
+            if diagnostics.is_empty() {
+                session.history.push(machine.tape().clone());
+                match machine.load_dump(instructions) {
+                    Ok(()) => println!("Loaded {}", path.display()),
+                    Err(e) => println!("Runtime error\nError :: {e}"),
+                }
+            } else {
+                let path = path.to_string_lossy();
+                print!("{}", render_all(&diagnostics, &source, Some(&path)));
+            }
+        }
+        _ if command.starts_with('+') => match number(&command[1..]) {
+            Some(name) => {
+                session.checkpoints.insert(name, machine.tape().clone());
+                println!("Saved checkpoint {}", name);
+            }
+            None => println!("Expected a checkpoint number"),
+        },
+        "//" => {
+            session.history.push(machine.tape().clone());
+            machine.restore(session.initial.clone());
+            println!("Reset the machine");
+        }
+        "/" => match session.history.pop() {
+            Some(tape) => {
+                machine.restore(tape);
+                println!("Undid the last change");
+            }
+            None => println!("Nothing to undo"),
+        },
+        _ if command.starts_with('/') => {
+            let name = command[1..].trim();
+            let tape =
+                number(name).and_then(|name| session.checkpoints.get(&name));
+            match tape {
+                Some(tape) => {
+                    session.history.push(machine.tape().clone());
+                    machine.restore(tape.clone());
+                    println!("Rolled back to checkpoint {}", name);
+                }
+                None => println!("No checkpoint {}", name),
+            }
+        }
+        "*" => {
+            let addresses: Vec<String> = machine
+                .tape()
+                .iter()
+                .map(|(address, _)| address.to_string())
+                .collect();
+            println!("Defined: {}", addresses.join(" "));
+        }
+        _ if command.starts_with('*') => match number(&command[1..]) {
+            Some(address) => match machine.tape().get(address) {
+                Some(expression) => {
+                    let instruction = Instruction {
+                        label: address,
+                        expression: expression.clone(),
+                        span: Span::default(),
+                    };
+                    println!("{}", deparse::deparse_instruction(&instruction));
+                }
+                None => println!("{} is undefined", address),
+            },
+            None => println!("Expected an address"),
+        },
+        _ if command.ends_with('-') => {
+            match number(&command[..command.len() - 1]) {
+                Some(address) => {
+                    session.history.push(machine.tape().clone());
+                    let instruction = Instruction {
+                        label: address,
+                        expression: Expression::Undefined,
+                        span: Span::default(),
+                    };
+                    machine.update(vec![instruction]);
+                    println!("Cleared {}", address);
+                }
+                None => println!("Expected an address"),
+            }
+        }
+        _ => match number(command) {
+            Some(address) => {
+                session.history.push(machine.tape().clone());
+                match machine.evaluate(address) {
+                    Ok(output) => println!("Output: {}", output),
+                    Err(e) => println!("Runtime error\nError :: {e}"),
+                }
+            }
+            None => println!("Unknown command, type --- for a list"),
+        },
+    }
+    false
+}
+
+fn render_all(
+    diagnostics: &[Diagnostic],
+    source: &str,
+    path: Option<&str>,
+) -> String {
+    let mut output = String::new();
+    for diagnostic in diagnostics {
+        output.push_str(&diagnostic.render(source, path));
+        output.push('\n');
+    }
+    match diagnostics.len() {
+        1 => output.push_str("error: aborting due to previous error\n"),
+        n => output.push_str(&format!("error: aborting due to {n} errors\n")),
+    }
+    output
+}
@@ -0,0 +1,88 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use crate::common::*;
+use crate::deparse::write_expression;
+use crate::diagnostic::Diagnostic;
+
+use std::collections::BTreeSet;
+
+/// Produce the shortest source code that parses to the same instructions,
+/// leaving out comments, whitespace, redundant nesting and instructions that
+/// are never referenced from the entry point. Only addresses that appear as
+/// literal numbers count as references, so instructions that are only
+/// reached through computed addresses are dropped as well.
+pub fn minify(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let (instructions, mut diagnostics) = crate::compile_all(source);
+    if !diagnostics.is_empty() {
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        return Err(diagnostics);
+    }
+
+    let mut reachable = BTreeSet::new();
+    let mut pending = vec![1];
+    while let Some(label) = pending.pop() {
+        if !reachable.insert(label) {
+            continue;
+        }
+        for instruction in instructions.iter() {
+            if instruction.label == label {
+                collect_addresses(&instruction.expression, &mut pending);
+            }
+        }
+    }
+
+    let mut output = String::new();
+    for instruction in instructions.iter() {
+        if reachable.contains(&instruction.label) {
+            output.push_str(&instruction.label.to_string());
+            output.push_str("..");
+            write_expression(&instruction.expression, true, &mut output);
+            output.push('\n');
+        }
+    }
+    Ok(output)
+}
+
+fn collect_addresses(expression: &Expression, addresses: &mut Vec<Integral>) {
+    match expression {
+        Expression::Number(number) if *number >= 0.5 => {
+            addresses.push(*number as Integral)
+        }
+        Expression::List(elements) => elements
+            .iter()
+            .for_each(|element| collect_addresses(element, addresses)),
+        Expression::Sequence(elements) => elements
+            .iter()
+            .for_each(|element| collect_addresses(element, addresses)),
+        Expression::Unary { operand, .. } => {
+            collect_addresses(operand, addresses)
+        }
+        Expression::Binary { left, right, .. } => {
+            collect_addresses(left, addresses);
+            collect_addresses(right, addresses);
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreachable_instructions_are_dropped() {
+        let source = "1 .. 2/3 (call)\n2 .. *0 (argument)\n4 .. 9\n";
+        assert_eq!(minify(source).unwrap(), "1..2/3\n2..*0\n");
+    }
+
+    #[test]
+    fn syntax_errors_are_reported() {
+        let diagnostics = minify("1 .. /.2 +").unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.column, 6);
+    }
+}
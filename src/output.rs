@@ -0,0 +1,58 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// Receives every character printed with `*.`.
+pub trait OutputSink {
+    fn print(&mut self, c: char) -> std::io::Result<()>;
+
+    fn flush(&mut self) -> std::io::Result<()>;
+}
+
+impl<W: Write> OutputSink for W {
+    fn print(&mut self, c: char) -> std::io::Result<()> {
+        let mut buffer = [0; 4];
+        self.write_all(c.encode_utf8(&mut buffer).as_bytes())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Write::flush(self)
+    }
+}
+
+/// An in-memory sink that can be cloned and shared between threads, so
+/// that the printed text can be inspected after handing a clone to a machine.
+#[derive(Debug, Clone, Default)]
+pub struct OutputBuffer {
+    text: Arc<Mutex<String>>,
+}
+
+impl OutputBuffer {
+    pub fn new() -> OutputBuffer {
+        OutputBuffer::default()
+    }
+
+    pub fn contents(&self) -> String {
+        self.text.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.text.lock().unwrap().clear();
+    }
+}
+
+impl OutputSink for OutputBuffer {
+    fn print(&mut self, c: char) -> std::io::Result<()> {
+        self.text.lock().unwrap().push(c);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -5,15 +5,14 @@
 //
 
 use crate::common::*;
+use crate::diagnostic::Diagnostic;
 use crate::lexer::LabelPass1;
 use crate::lexer::TokenTreePass1;
 
 use itertools::Itertools;
 use log::*;
 
-pub fn parse(
-    labels: Vec<LabelPass1>,
-) -> Result<Vec<Instruction>, anyhow::Error> {
+pub fn parse(labels: Vec<LabelPass1>) -> Result<Vec<Instruction>, Diagnostic> {
     trace!("");
     labels
         .into_iter()
@@ -22,25 +21,48 @@ pub fn parse(
         .collect()
 }
 
+/// Parse every label, returning the instructions that parsed along with
+/// the errors in the labels that did not.
+pub fn parse_all(
+    labels: Vec<LabelPass1>,
+) -> (Vec<Instruction>, Vec<Diagnostic>) {
+    trace!("");
+    labels
+        .into_iter()
+        .filter(|LabelPass1(tokens)| !tokens.is_empty())
+        .map(|LabelPass1(tokens)| parse_instruction(tokens))
+        .partition_result()
+}
+
 fn parse_instruction(
-    tokens: Vec<TokenTreePass1>,
-) -> Result<Instruction, anyhow::Error> {
+    tokens: Vec<Spanned<TokenTreePass1>>,
+) -> Result<Instruction, Diagnostic> {
+    let span = match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => unreachable!(),
+    };
     let mut tokens = tokens.into_iter().peekable();
     let label = match tokens.next() {
-        Some(TokenTreePass1::Int(label)) => label,
-        Some(_) => Err(Error::InvalidLabel)?,
+        Some(Spanned {
+            value: TokenTreePass1::Int(label),
+            ..
+        }) => label,
+        Some(Spanned { span, .. }) => Err(Error::InvalidLabel { span })?,
         None => unreachable!(),
     };
     let mut intermediates = Vec::new();
     while let Some(separator) = tokens.next() {
-        match separator {
+        match separator.value {
             TokenTreePass1::Sep => (),
-            other => Err(Error::ExpectedSeparatorInInsruction { got: other })?,
+            other => Err(Error::ExpectedSeparatorInInsruction {
+                got: other,
+                span: separator.span,
+            })?,
         }
         let tokens = tokens
             .by_ref()
             .peeking_take_while(|token| !is_separator(token));
-        let expression = parse_expression(tokens)?;
+        let expression = parse_expression(tokens, separator.span)?;
         intermediates.push(expression);
     }
     let expression = if intermediates.len() > 1 {
@@ -48,35 +70,43 @@ fn parse_instruction(
     } else {
         intermediates
             .pop()
-            .ok_or_else(|| Error::ExpectedExpression)?
+            .ok_or(Error::ExpectedExpression { span })?
     };
     trace!("{}:\t{}", label, expression);
-    let instruction = Instruction { label, expression };
+    let instruction = Instruction {
+        label,
+        expression,
+        span,
+    };
     Ok(instruction)
 }
 
 fn parse_expression(
-    mut tokens: impl std::iter::Iterator<Item = TokenTreePass1>,
-) -> Result<Expression, anyhow::Error> {
+    mut tokens: impl std::iter::Iterator<Item = Spanned<TokenTreePass1>>,
+    preceding_span: Span,
+) -> Result<Expression, Diagnostic> {
     let mut expression = None;
     let mut stacked_unaries = Vec::new();
-    while let Some(token) = tokens.next() {
+    let mut last_span = preceding_span;
+    while let Some(Spanned { value: token, span }) = tokens.next() {
+        last_span = span;
         match token {
             TokenTreePass1::Unary(unary) => {
-                stacked_unaries.push(unary);
+                stacked_unaries.push((unary, span));
             }
             TokenTreePass1::Binary(binary) => {
                 let left = expression
-                    .ok_or_else(|| Error::ExpectedExpressionBeforeBinary)?;
-                let right = parse_expression(tokens)?;
+                    .ok_or(Error::ExpectedExpressionBeforeBinary { span })?;
+                let right = parse_expression(tokens, span)?;
                 expression = Some(Expression::Binary {
                     operator: binary,
                     left: Box::new(left),
                     right: Box::new(right),
+                    span,
                 });
                 break;
             }
-            _ if expression.is_some() => Err(Error::ExpectedOperator)?,
+            _ if expression.is_some() => Err(Error::ExpectedOperator { span })?,
             TokenTreePass1::Int(integral) => {
                 expression = Some(Expression::Number(integral as Float));
             }
@@ -85,52 +115,82 @@ fn parse_expression(
             }
             TokenTreePass1::NestExpr(mut tokens) => {
                 if tokens.is_empty() || tokens.iter().any(is_separator) {
-                    let elements: Result<Vec<Expression>, anyhow::Error> =
-                        tokens
-                            .split_mut(is_separator)
-                            .filter(|tokens| !tokens.is_empty())
-                            .map(|tokens| {
-                                let tokens = tokens.to_vec();
-                                parse_expression(tokens.into_iter())
-                            })
-                            .collect();
+                    let elements: Result<Vec<Expression>, Diagnostic> = tokens
+                        .split_mut(is_separator)
+                        .filter(|tokens| !tokens.is_empty())
+                        .map(|tokens| {
+                            let tokens = tokens.to_vec();
+                            parse_expression(tokens.into_iter(), span)
+                        })
+                        .collect();
                     let elements = elements?;
                     expression = Some(Expression::List(elements));
                 } else {
-                    let inner = parse_expression(tokens.into_iter())?;
+                    let inner = parse_expression(tokens.into_iter(), span)?;
                     expression = Some(inner);
                 }
             }
-            TokenTreePass1::Sep => Err(Error::ExpectedOperator)?,
+            TokenTreePass1::Sep => Err(Error::ExpectedOperator { span })?,
         }
     }
-    let mut expression = expression.ok_or_else(|| Error::ExpectedExpression)?;
-    while let Some(unary) = stacked_unaries.pop() {
+    let mut expression =
+        expression.ok_or(Error::ExpectedExpression { span: last_span })?;
+    while let Some((unary, span)) = stacked_unaries.pop() {
         expression = Expression::Unary {
             operator: unary,
             operand: Box::new(expression),
+            span,
         };
     }
     Ok(expression)
 }
 
-fn is_separator(token: &TokenTreePass1) -> bool {
-    match token {
-        TokenTreePass1::Sep => true,
-        _ => false,
-    }
+fn is_separator(token: &Spanned<TokenTreePass1>) -> bool {
+    matches!(token.value, TokenTreePass1::Sep)
 }
 
 #[derive(Debug, thiserror::Error)]
 enum Error {
     #[error("Expected separator, got {got:?}")]
-    ExpectedSeparatorInInsruction { got: TokenTreePass1 },
+    ExpectedSeparatorInInsruction { got: TokenTreePass1, span: Span },
     #[error("Expected expression")]
-    ExpectedExpression,
+    ExpectedExpression { span: Span },
     #[error("Expected expression before binary operator")]
-    ExpectedExpressionBeforeBinary,
+    ExpectedExpressionBeforeBinary { span: Span },
     #[error("Expected binary operator")]
-    ExpectedOperator,
+    ExpectedOperator { span: Span },
     #[error("Invalid label, must be integral")]
-    InvalidLabel,
+    InvalidLabel { span: Span },
+}
+
+impl From<Error> for Diagnostic {
+    fn from(error: Error) -> Diagnostic {
+        let (code, span, hint) = match &error {
+            Error::ExpectedSeparatorInInsruction { span, .. } => {
+                ("E0201", *span, "did you mean `..` here?")
+            }
+            Error::ExpectedExpression { span } => {
+                ("E0202", *span, "add an expression after this")
+            }
+            Error::ExpectedExpressionBeforeBinary { span } => (
+                "E0203",
+                *span,
+                "binary operators need an expression on both sides",
+            ),
+            Error::ExpectedOperator { span } => (
+                "E0204",
+                *span,
+                "use `..` to separate statements, or add an operator",
+            ),
+            Error::InvalidLabel { span } => {
+                ("E0205", *span, "labels are whole numbers such as `1`")
+            }
+        };
+        Diagnostic {
+            code,
+            message: error.to_string(),
+            span,
+            hint: Some(hint),
+        }
+    }
 }
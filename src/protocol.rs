@@ -0,0 +1,44 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use serde_json::Value;
+use std::io::{BufRead, Write};
+
+/// Read a JSON message that is preceded by a `Content-Length` header, as
+/// used by both the Debug Adapter Protocol and the Language Server Protocol.
+/// Returns None at the end of the input.
+pub fn read_message(
+    reader: &mut impl BufRead,
+) -> anyhow::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length = length
+        .ok_or_else(|| anyhow::anyhow!("Missing Content-Length header"))?;
+    let mut buffer = vec![0; length];
+    reader.read_exact(&mut buffer)?;
+    Ok(Some(serde_json::from_slice(&buffer)?))
+}
+
+/// Write a JSON message to stdout, preceded by a `Content-Length` header.
+pub fn write_message(message: &Value) -> anyhow::Result<()> {
+    let text = message.to_string();
+    let mut stdout = std::io::stdout().lock();
+    write!(stdout, "Content-Length: {}\r\n\r\n{}", text.len(), text)?;
+    stdout.flush()?;
+    Ok(())
+}
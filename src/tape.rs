@@ -0,0 +1,65 @@
+//
+// Part of Numpad
+// Copyright (c) 2023 Remy Pierre Bushnell Clarke & Sander in 't Veld
+// License: MIT
+//
+
+use crate::common::*;
+
+use std::collections::BTreeMap;
+
+/// A sparse tape of expressions, indexed by address.
+/// Addresses that were never written to (or were set to undefined) take up
+/// no space, so far-apart addresses cost nothing.
+#[derive(Debug, Clone, Default)]
+pub struct Tape {
+    cells: BTreeMap<usize, Expression>,
+}
+
+impl Tape {
+    pub fn new() -> Tape {
+        Tape::default()
+    }
+
+    /// Get the expression at the given address, or None if it is undefined.
+    pub fn get(&self, address: usize) -> Option<&Expression> {
+        self.cells.get(&address)
+    }
+
+    pub fn get_mut(&mut self, address: usize) -> Option<&mut Expression> {
+        self.cells.get_mut(&address)
+    }
+
+    /// Store an expression at the given address. Storing undefined frees
+    /// the cell.
+    pub fn set(&mut self, address: usize, expression: Expression) {
+        match expression {
+            Expression::Undefined => {
+                self.cells.remove(&address);
+            }
+            expression => {
+                self.cells.insert(address, expression);
+            }
+        }
+    }
+
+    pub fn is_defined(&self, address: usize) -> bool {
+        self.cells.contains_key(&address)
+    }
+
+    /// The number of addresses that are not undefined.
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// Iterate over all defined addresses in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Expression)> {
+        self.cells
+            .iter()
+            .map(|(address, expression)| (*address, expression))
+    }
+}